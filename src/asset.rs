@@ -0,0 +1,44 @@
+use crate::error::ValidationError;
+use serde::{Deserialize, Serialize};
+
+/// Symbol of the chain's native coin, whose balances live in `Storage::accounts`.
+pub const NATIVE_ASSET: &str = "B";
+
+/// Largest number of decimals an asset may declare.
+pub const MAX_DECIMALS: u8 = 18;
+
+/// Metadata and supply of an asset tracked by the ledger.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Asset {
+    pub symbol: String,
+    /// Display precision only; balances are always stored in base units.
+    pub decimals: u8,
+    /// Account that issued the asset, `None` for the native coin.
+    pub issuer: Option<String>,
+    pub minted: u64,
+    pub burned: u64,
+}
+
+impl Asset {
+    /// Units in circulation, or `None` if more were burned than minted,
+    /// which only an inconsistent ledger records.
+    pub fn supply(&self) -> Option<u64> {
+        self.minted.checked_sub(self.burned)
+    }
+}
+
+/// Symbols are 1 to 12 uppercase ASCII letters or digits.
+pub fn validate_symbol(symbol: &str) -> Result<(), ValidationError> {
+    let valid = (1..=12).contains(&symbol.len())
+        && symbol
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit());
+    if !valid {
+        return Err(ValidationError::InvalidSymbol(symbol.to_string()));
+    }
+    Ok(())
+}
+
+pub(crate) fn native_asset() -> String {
+    NATIVE_ASSET.to_string()
+}
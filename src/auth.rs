@@ -0,0 +1,157 @@
+//! Access control for the node's command port.
+//!
+//! A node started with a token file only serves sessions that open with an
+//! `auth <token>` line, followed by the command or binary message. Each token
+//! grants a scope, and every command needs a minimum scope:
+//!
+//! - `read` for queries,
+//! - `submit` to also submit transactions,
+//! - `admin` to also run node commands such as `mine`.
+//!
+//! A token can also be bound to an account. Minting and burning are reserved
+//! to an asset's authority, so they are only accepted from a session bound to
+//! that account, or from an unbound `admin` session.
+//!
+//! Without a token file every session has the `admin` scope, which suits a
+//! local development node.
+
+use crate::error::{Result, StorageError, ValidationError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// First word of the line that carries a session's token.
+pub const HEADER: &str = "auth";
+
+/// What a session is allowed to do. Each scope includes the ones before it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Read,
+    Submit,
+    Admin,
+}
+
+impl Scope {
+    /// Scope needed to run the command named `command`.
+    pub fn required_for(command: &str) -> Scope {
+        match command {
+            "create-account" | "transfer" | "issue-asset" | "transfer-asset" | "mint" | "burn"
+            | "submit-tx" => Scope::Submit,
+            "mine" | "advance-time" | "rewind" => Scope::Admin,
+            _ => Scope::Read,
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Scope::Read => "read",
+            Scope::Submit => "submit",
+            Scope::Admin => "admin",
+        })
+    }
+}
+
+/// What a session may do: its scope and, if its token is bound to one, the
+/// account it acts for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Grant {
+    pub scope: Scope,
+    pub account: Option<String>,
+}
+
+impl Grant {
+    /// Whether the session may act as `account` where only that account may,
+    /// as when minting or burning. Unbound sessions need the `admin` scope.
+    pub fn may_act_for(&self, account: &str) -> bool {
+        match &self.account {
+            Some(bound) => bound == account,
+            None => self.scope == Scope::Admin,
+        }
+    }
+}
+
+impl From<Scope> for Grant {
+    fn from(scope: Scope) -> Self {
+        Grant {
+            scope,
+            account: None,
+        }
+    }
+}
+
+/// Tokens accepted by a node, loaded from a JSON file such as
+/// `{"anonymous": "read", "tokens": {"s3cret": "admin"}}`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct AuthConfig {
+    /// Scope of sessions without a token. They are refused when unset.
+    #[serde(default)]
+    pub anonymous: Option<Scope>,
+    pub tokens: HashMap<String, Scope>,
+    /// Account each bound token acts for, e.g. `{"c3ntral": "treasury"}`.
+    #[serde(default)]
+    pub accounts: HashMap<String, String>,
+}
+
+impl AuthConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path.as_ref()).map_err(|source| StorageError::Io {
+            path: path.as_ref().to_path_buf(),
+            source,
+        })?;
+        Ok(serde_json::from_reader(BufReader::new(file)).map_err(StorageError::from)?)
+    }
+
+    /// Grant of a session that presented `token`, if any.
+    pub fn authorize(&self, token: Option<&str>) -> Result<Grant> {
+        match token {
+            Some(token) => {
+                let scope = self
+                    .tokens
+                    .get(token)
+                    .copied()
+                    .ok_or(ValidationError::InvalidToken)?;
+                Ok(Grant {
+                    scope,
+                    account: self.accounts.get(token).cloned(),
+                })
+            }
+            None => self
+                .anonymous
+                .map(Grant::from)
+                .ok_or_else(|| ValidationError::Unauthenticated.into()),
+        }
+    }
+}
+
+/// Splits the `auth <token>` line off the start of a session, returning the
+/// token and the rest of the request.
+pub fn split_header(request: &[u8]) -> (Option<&str>, &[u8]) {
+    let Some(line_end) = request.iter().position(|&byte| byte == b'\n') else {
+        return (None, request);
+    };
+    let token = std::str::from_utf8(&request[..line_end])
+        .ok()
+        .and_then(|line| line.trim().strip_prefix(HEADER))
+        .filter(|rest| rest.starts_with(' '))
+        .map(str::trim);
+    match token {
+        Some(token) => (Some(token), &request[line_end + 1..]),
+        None => (None, request),
+    }
+}
+
+/// Prefixes `request` with the header carrying `token`.
+pub fn with_header(token: Option<&str>, request: &[u8]) -> Vec<u8> {
+    let mut session = Vec::new();
+    if let Some(token) = token {
+        session.extend_from_slice(format!("{} {}\n", HEADER, token).as_bytes());
+    }
+    session.extend_from_slice(request);
+    session
+}
@@ -0,0 +1,175 @@
+use crate::auth;
+use crate::encoding;
+use crate::error::{Error, NetworkError};
+use crate::message::Message;
+use crate::receipt::{Receipt, ReceiptStatus, TxStatus};
+use crate::transport::{SecureStream, StaticKey};
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::time::{Duration, Instant};
+
+/// Address a node listens on unless configured otherwise.
+pub const DEFAULT_ADDR: &str = "127.0.0.1:3000";
+
+/// How often `wait_for_transaction` asks the node for news.
+pub const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Final state of a transaction awaited with `wait_for_transaction`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settled {
+    pub status: TxStatus,
+    pub receipt: ReceiptStatus,
+    /// Height of the block that includes the transaction.
+    pub height: u64,
+    /// Blocks from the transaction's block to the tip, both included.
+    pub confirmations: u64,
+}
+
+/// How a client opens its sessions with a node.
+#[derive(Clone, Debug, Default)]
+pub struct Session {
+    /// Auth token sent in the header of each session.
+    pub token: Option<String>,
+    /// Encrypts each session when set.
+    pub encryption: Option<Encryption>,
+}
+
+/// Settings of encrypted sessions.
+#[derive(Clone, Debug)]
+pub struct Encryption {
+    /// Static key presented to the node.
+    pub key: StaticKey,
+    /// Public key the node must present, when known.
+    pub node_key: Option<Vec<u8>>,
+}
+
+impl Session {
+    pub fn with_token(token: &str) -> Self {
+        Session {
+            token: Some(token.to_string()),
+            ..Session::default()
+        }
+    }
+}
+
+/// Sends one command to the node at `addr` and returns its raw response.
+pub fn send_command(addr: &str, command: &str) -> Result<String, NetworkError> {
+    send_command_as(addr, &Session::default(), command)
+}
+
+/// Like `send_command`, in a session set up as `session` says.
+pub fn send_command_as(
+    addr: &str,
+    session: &Session,
+    command: &str,
+) -> Result<String, NetworkError> {
+    let response = exchange(addr, session, command.trim_end().as_bytes())?;
+    Ok(String::from_utf8_lossy(&response).into_owned())
+}
+
+/// Sends one binary message to the node at `addr` and decodes its reply.
+pub fn send_message(addr: &str, message: &Message) -> Result<Message, NetworkError> {
+    send_message_as(addr, &Session::default(), message)
+}
+
+/// Like `send_message`, in a session set up as `session` says.
+pub fn send_message_as(
+    addr: &str,
+    session: &Session,
+    message: &Message,
+) -> Result<Message, NetworkError> {
+    let response = exchange(addr, session, &encoding::encode(message))?;
+    Ok(encoding::decode(&response)?)
+}
+
+/// Sends one request and reads the whole response.
+fn exchange(addr: &str, session: &Session, request: &[u8]) -> Result<Vec<u8>, NetworkError> {
+    let mut stream = TcpStream::connect(addr)?;
+    let request = auth::with_header(session.token.as_deref(), request);
+    if let Some(encryption) = &session.encryption {
+        let mut stream =
+            SecureStream::connect(stream, &encryption.key, encryption.node_key.as_deref())?;
+        stream.send(&request)?;
+        return stream.receive();
+    }
+    // Signal the end of the request so the node can reply
+    let sent = stream
+        .write_all(&request)
+        .and_then(|()| stream.flush())
+        .and_then(|()| stream.shutdown(Shutdown::Write));
+
+    // A node refusing the request may answer and close before reading it all
+    let mut response = Vec::new();
+    let received = stream.read_to_end(&mut response);
+    if response.is_empty() {
+        sent?;
+        received?;
+    }
+    Ok(response)
+}
+
+/// Id of the transaction a submitting command's response reports.
+pub fn submitted_tx_id(response: &str) -> Option<String> {
+    let response: serde_json::Value = serde_json::from_str(response).ok()?;
+    response["tx_id"].as_str().map(str::to_string)
+}
+
+/// Polls the node until transaction `tx_id` is included with at least
+/// `confirmations` blocks, or fails or is dropped. Confirmations only accrue
+/// as later blocks are mined, so an idle chain can run into the `timeout`.
+pub fn wait_for_transaction(
+    addr: &str,
+    session: &Session,
+    tx_id: &str,
+    confirmations: u64,
+    timeout: Duration,
+) -> Result<Settled, NetworkError> {
+    let started = Instant::now();
+    loop {
+        let tx = query_json(addr, session, &format!("get-tx {}", tx_id))?;
+        if let (Some(height), Ok(receipt)) = (
+            tx["block_height"].as_u64(),
+            serde_json::from_value::<ReceiptStatus>(tx["receipt"].clone()),
+        ) {
+            let tip = query_json(addr, session, "height")?["height"]
+                .as_u64()
+                .ok_or(NetworkError::UnexpectedMessage)?;
+            let settled = Settled {
+                status: TxStatus::from_receipt(
+                    height,
+                    &Receipt {
+                        tx_id: tx_id.to_string(),
+                        status: receipt.clone(),
+                    },
+                ),
+                receipt,
+                height,
+                confirmations: (tip + 1).saturating_sub(height),
+            };
+            let failed = !matches!(settled.status, TxStatus::Confirmed { .. });
+            if failed || settled.confirmations >= confirmations {
+                return Ok(settled);
+            }
+        }
+        if started.elapsed() >= timeout {
+            return Err(NetworkError::Timeout(timeout));
+        }
+        std::thread::sleep(WAIT_POLL_INTERVAL.min(timeout.saturating_sub(started.elapsed())));
+    }
+}
+
+/// Sends a command whose successful response is JSON.
+fn query_json(
+    addr: &str,
+    session: &Session,
+    command: &str,
+) -> Result<serde_json::Value, NetworkError> {
+    let response = send_command_as(addr, session, command)?;
+    if let Some((code, message)) = Error::parse_wire(&response) {
+        return Err(NetworkError::Remote {
+            code: code.to_string(),
+            message: message.to_string(),
+        });
+    }
+    serde_json::from_str(&response).map_err(|_| NetworkError::UnexpectedMessage)
+}
@@ -0,0 +1,479 @@
+//! Canonical binary encoding of blocks, transactions, genesis configurations
+//! and network messages.
+//!
+//! Every value has exactly one encoding, so it can be hashed. Integers are
+//! LEB128 varints, strings and lists are prefixed with their length, and enum
+//! variants with a one-byte tag. Encoded data starts with a version byte so the
+//! format can evolve. The JSON forms stay in use for human-facing output.
+
+use crate::error::DecodeError;
+use crate::genesis::{ConsensusParams, GenesisConfig};
+use crate::receipt::{Receipt, ReceiptStatus};
+use crate::{Block, BlockHeader, Transaction};
+
+/// Version byte written in front of every encoded value.
+pub const VERSION: u8 = 1;
+
+pub trait Encode {
+    fn encode_to(&self, out: &mut Vec<u8>);
+}
+
+pub trait Decode: Sized {
+    fn decode_from(input: &mut Decoder<'_>) -> Result<Self, DecodeError>;
+}
+
+/// Encodes `value`, version byte included.
+pub fn encode<T: Encode + ?Sized>(value: &T) -> Vec<u8> {
+    let mut out = vec![VERSION];
+    value.encode_to(&mut out);
+    out
+}
+
+/// Decodes a value written by `encode`. The whole input must be consumed.
+pub fn decode<T: Decode>(bytes: &[u8]) -> Result<T, DecodeError> {
+    let mut input = Decoder { input: bytes };
+    let version = input.byte()?;
+    if version != VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+    let value = T::decode_from(&mut input)?;
+    if !input.input.is_empty() {
+        return Err(DecodeError::TrailingBytes(input.input.len()));
+    }
+    Ok(value)
+}
+
+/// Appends `value` to `out` as a length-prefixed frame, for logs of values.
+pub fn write_frame<T: Encode + ?Sized>(value: &T, out: &mut Vec<u8>) {
+    let encoded = encode(value);
+    (encoded.len() as u64).encode_to(out);
+    out.extend_from_slice(&encoded);
+}
+
+/// Decodes every frame of a log written with `write_frame`.
+pub fn read_frames<T: Decode>(bytes: &[u8]) -> Result<Vec<T>, DecodeError> {
+    let mut input = Decoder { input: bytes };
+    let mut values = Vec::new();
+    while !input.input.is_empty() {
+        let len = input.len()?;
+        values.push(decode(input.bytes(len)?)?);
+    }
+    Ok(values)
+}
+
+/// Cursor over encoded bytes.
+pub struct Decoder<'a> {
+    input: &'a [u8],
+}
+
+impl Decoder<'_> {
+    pub fn byte(&mut self) -> Result<u8, DecodeError> {
+        let (&byte, rest) = self.input.split_first().ok_or(DecodeError::UnexpectedEnd)?;
+        self.input = rest;
+        Ok(byte)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&[u8], DecodeError> {
+        if len > self.input.len() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(bytes)
+    }
+
+    fn len(&mut self) -> Result<usize, DecodeError> {
+        let len = u64::decode_from(self)?;
+        usize::try_from(len).map_err(|_| DecodeError::UnexpectedEnd)
+    }
+}
+
+impl Encode for u8 {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+}
+
+impl Decode for u8 {
+    fn decode_from(input: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        input.byte()
+    }
+}
+
+impl Encode for u64 {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        let mut value = *self;
+        while value >= 0x80 {
+            out.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+}
+
+impl Decode for u64 {
+    fn decode_from(input: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = input.byte()?;
+            let bits = u64::from(byte & 0x7f);
+            // Reject overlong and overflowing forms, so each value has one encoding
+            if (shift == 63 && bits > 1) || (shift > 0 && byte == 0) {
+                return Err(DecodeError::InvalidTag {
+                    kind: "varint",
+                    tag: byte,
+                });
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::UnexpectedEnd)
+    }
+}
+
+impl Encode for str {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        (self.len() as u64).encode_to(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Encode for String {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.as_str().encode_to(out);
+    }
+}
+
+impl Decode for String {
+    fn decode_from(input: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        let len = input.len()?;
+        let bytes = input.bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+}
+
+impl Encode for bool {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.push(u8::from(*self));
+    }
+}
+
+impl Decode for bool {
+    fn decode_from(input: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        match input.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(DecodeError::InvalidTag { kind: "bool", tag }),
+        }
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(value) => {
+                out.push(1);
+                value.encode_to(out);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode_from(input: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        match input.byte()? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode_from(input)?)),
+            tag => Err(DecodeError::InvalidTag {
+                kind: "option",
+                tag,
+            }),
+        }
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        (self.len() as u64).encode_to(out);
+        for item in self {
+            item.encode_to(out);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode_to(out);
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode_from(input: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        let len = input.len()?;
+        // Every item takes at least one byte, which bounds the allocation
+        if len > input.input.len() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        (0..len).map(|_| T::decode_from(input)).collect()
+    }
+}
+
+impl Encode for ConsensusParams {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.block_time_secs.encode_to(out);
+        (self.max_block_bytes as u64).encode_to(out);
+        (self.max_block_transactions as u64).encode_to(out);
+        self.finality_depth.encode_to(out);
+    }
+}
+
+impl Encode for GenesisConfig {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.chain_id.encode_to(out);
+        self.timestamp.encode_to(out);
+        self.consensus.encode_to(out);
+        self.authority.encode_to(out);
+        // Allocations are sorted by account, which keeps the encoding unique
+        (self.allocations.len() as u64).encode_to(out);
+        for (account, amount) in &self.allocations {
+            account.encode_to(out);
+            amount.encode_to(out);
+        }
+    }
+}
+
+impl Encode for Transaction {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            Transaction::Transfer {
+                chain_id,
+                from,
+                to,
+                amount,
+                nonce,
+            } => {
+                out.push(0);
+                chain_id.encode_to(out);
+                from.encode_to(out);
+                to.encode_to(out);
+                amount.encode_to(out);
+                nonce.encode_to(out);
+            }
+            Transaction::CreateAccount {
+                chain_id,
+                id,
+                balance,
+            } => {
+                out.push(1);
+                chain_id.encode_to(out);
+                id.encode_to(out);
+                balance.encode_to(out);
+            }
+            Transaction::IssueAsset {
+                chain_id,
+                issuer,
+                symbol,
+                decimals,
+                supply,
+            } => {
+                out.push(2);
+                chain_id.encode_to(out);
+                issuer.encode_to(out);
+                symbol.encode_to(out);
+                decimals.encode_to(out);
+                supply.encode_to(out);
+            }
+            Transaction::TransferAsset {
+                chain_id,
+                from,
+                to,
+                asset,
+                amount,
+                nonce,
+            } => {
+                out.push(3);
+                chain_id.encode_to(out);
+                from.encode_to(out);
+                to.encode_to(out);
+                asset.encode_to(out);
+                amount.encode_to(out);
+                nonce.encode_to(out);
+            }
+            Transaction::Mint {
+                chain_id,
+                authority,
+                to,
+                asset,
+                amount,
+                nonce,
+            } => {
+                out.push(4);
+                chain_id.encode_to(out);
+                authority.encode_to(out);
+                to.encode_to(out);
+                asset.encode_to(out);
+                amount.encode_to(out);
+                nonce.encode_to(out);
+            }
+            Transaction::Burn {
+                chain_id,
+                authority,
+                asset,
+                amount,
+                nonce,
+            } => {
+                out.push(5);
+                chain_id.encode_to(out);
+                authority.encode_to(out);
+                asset.encode_to(out);
+                amount.encode_to(out);
+                nonce.encode_to(out);
+            }
+        }
+    }
+}
+
+impl Decode for Transaction {
+    fn decode_from(input: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        let transaction = match input.byte()? {
+            0 => Transaction::Transfer {
+                chain_id: String::decode_from(input)?,
+                from: String::decode_from(input)?,
+                to: String::decode_from(input)?,
+                amount: u64::decode_from(input)?,
+                nonce: u64::decode_from(input)?,
+            },
+            1 => Transaction::CreateAccount {
+                chain_id: String::decode_from(input)?,
+                id: String::decode_from(input)?,
+                balance: u64::decode_from(input)?,
+            },
+            2 => Transaction::IssueAsset {
+                chain_id: String::decode_from(input)?,
+                issuer: String::decode_from(input)?,
+                symbol: String::decode_from(input)?,
+                decimals: u8::decode_from(input)?,
+                supply: u64::decode_from(input)?,
+            },
+            3 => Transaction::TransferAsset {
+                chain_id: String::decode_from(input)?,
+                from: String::decode_from(input)?,
+                to: String::decode_from(input)?,
+                asset: String::decode_from(input)?,
+                amount: u64::decode_from(input)?,
+                nonce: u64::decode_from(input)?,
+            },
+            4 => Transaction::Mint {
+                chain_id: String::decode_from(input)?,
+                authority: String::decode_from(input)?,
+                to: String::decode_from(input)?,
+                asset: String::decode_from(input)?,
+                amount: u64::decode_from(input)?,
+                nonce: u64::decode_from(input)?,
+            },
+            5 => Transaction::Burn {
+                chain_id: String::decode_from(input)?,
+                authority: String::decode_from(input)?,
+                asset: String::decode_from(input)?,
+                amount: u64::decode_from(input)?,
+                nonce: u64::decode_from(input)?,
+            },
+            tag => {
+                return Err(DecodeError::InvalidTag {
+                    kind: "transaction",
+                    tag,
+                })
+            }
+        };
+        Ok(transaction)
+    }
+}
+
+impl Encode for Receipt {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.tx_id.encode_to(out);
+        match &self.status {
+            ReceiptStatus::Included => out.push(0),
+            ReceiptStatus::Failed { reason } => {
+                out.push(1);
+                reason.encode_to(out);
+            }
+            ReceiptStatus::Dropped { reason } => {
+                out.push(2);
+                reason.encode_to(out);
+            }
+        }
+    }
+}
+
+impl Decode for Receipt {
+    fn decode_from(input: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        let tx_id = String::decode_from(input)?;
+        let status = match input.byte()? {
+            0 => ReceiptStatus::Included,
+            1 => ReceiptStatus::Failed {
+                reason: String::decode_from(input)?,
+            },
+            2 => ReceiptStatus::Dropped {
+                reason: String::decode_from(input)?,
+            },
+            tag => {
+                return Err(DecodeError::InvalidTag {
+                    kind: "receipt",
+                    tag,
+                })
+            }
+        };
+        Ok(Receipt { tx_id, status })
+    }
+}
+
+impl Encode for Block {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.chain_id.encode_to(out);
+        self.timestamp.encode_to(out);
+        self.transactions.encode_to(out);
+        self.receipts.encode_to(out);
+        self.prev_block_hash.encode_to(out);
+        self.nonce.encode_to(out);
+    }
+}
+
+impl Decode for Block {
+    fn decode_from(input: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        Ok(Block {
+            chain_id: String::decode_from(input)?,
+            timestamp: u64::decode_from(input)?,
+            transactions: Vec::decode_from(input)?,
+            receipts: Vec::decode_from(input)?,
+            prev_block_hash: String::decode_from(input)?,
+            nonce: u64::decode_from(input)?,
+            pruned: None,
+        })
+    }
+}
+
+impl Encode for BlockHeader {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.chain_id.encode_to(out);
+        self.timestamp.encode_to(out);
+        self.prev_block_hash.encode_to(out);
+        self.nonce.encode_to(out);
+        self.hash.encode_to(out);
+    }
+}
+
+impl Decode for BlockHeader {
+    fn decode_from(input: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        Ok(BlockHeader {
+            chain_id: String::decode_from(input)?,
+            timestamp: u64::decode_from(input)?,
+            prev_block_hash: String::decode_from(input)?,
+            nonce: u64::decode_from(input)?,
+            hash: String::decode_from(input)?,
+        })
+    }
+}
@@ -0,0 +1,331 @@
+use crate::auth::Scope;
+use std::io;
+use std::num::ParseIntError;
+use std::path::PathBuf;
+use std::time::Duration;
+use thiserror::Error;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Top-level error returned by the library. Each category wraps a more
+/// specific error, and every error maps to a stable code used on the wire.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Validation(#[from] ValidationError),
+    #[error(transparent)]
+    NotFound(#[from] NotFoundError),
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error(transparent)]
+    Network(#[from] NetworkError),
+    #[error(transparent)]
+    Consensus(#[from] ConsensusError),
+}
+
+impl Error {
+    pub fn category(&self) -> &'static str {
+        match self {
+            Error::Validation(_) => "validation",
+            Error::NotFound(_) => "not_found",
+            Error::Storage(_) => "storage",
+            Error::Network(_) => "network",
+            Error::Consensus(_) => "consensus",
+        }
+    }
+
+    /// Stable machine-readable code. Codes are never renamed once released.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Validation(e) => e.code(),
+            Error::NotFound(e) => e.code(),
+            Error::Storage(e) => e.code(),
+            Error::Network(e) => e.code(),
+            Error::Consensus(e) => e.code(),
+        }
+    }
+
+    /// Representation sent back to clients, e.g. `Error [insufficient_funds]: Insufficient funds`.
+    pub fn to_wire(&self) -> String {
+        format!("Error [{}]: {}", self.code(), self)
+    }
+
+    /// Splits a response produced by `to_wire` back into its code and
+    /// message. Returns `None` for successful responses.
+    pub fn parse_wire(response: &str) -> Option<(&str, &str)> {
+        let (code, message) = response.strip_prefix("Error [")?.split_once("]: ")?;
+        Some((code, message.trim_end()))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ValidationError {
+    #[error("Invalid command")]
+    InvalidCommand,
+    #[error("Invalid {field}: '{value}'")]
+    InvalidNumber {
+        field: &'static str,
+        value: String,
+        #[source]
+        source: ParseIntError,
+    },
+    #[error("Page and page size must be positive")]
+    InvalidPage,
+    #[error("Insufficient funds")]
+    InsufficientFunds,
+    #[error("Account already exists")]
+    AccountExists,
+    #[error("Transaction already pending")]
+    DuplicateTransaction,
+    #[error("Blockchain node is not running")]
+    NodeInactive,
+    #[error("Arithmetic overflow")]
+    Overflow,
+    #[error("Invalid asset symbol '{0}'")]
+    InvalidSymbol(String),
+    #[error("Asset decimals must be at most {max}")]
+    InvalidDecimals { max: u8 },
+    #[error("Asset '{0}' already exists")]
+    AssetExists(String),
+    #[error("Account '{account}' is not authorized to mint or burn {asset}")]
+    Unauthorized { account: String, asset: String },
+    #[error("New accounts must start with a zero balance on this network")]
+    InitialBalanceNotAllowed,
+    #[error("Transaction is for chain '{actual}', this node runs '{expected}'")]
+    WrongChain { expected: String, actual: String },
+    #[error("Transaction of {size} bytes exceeds the block size limit of {max}")]
+    TransactionTooLarge { size: usize, max: usize },
+    #[error("This node requires an auth token")]
+    Unauthenticated,
+    #[error("Unknown auth token")]
+    InvalidToken,
+    #[error("'{command}' needs the {required} scope")]
+    PermissionDenied { command: String, required: Scope },
+    #[error("This session may not act for account '{0}'")]
+    AccountNotBound(String),
+    #[error("Cannot rewind to height {height}, the tip is at {tip}")]
+    InvalidRewind { height: u64, tip: u64 },
+}
+
+impl ValidationError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ValidationError::InvalidCommand => "invalid_command",
+            ValidationError::InvalidNumber { .. } => "invalid_number",
+            ValidationError::InvalidPage => "invalid_page",
+            ValidationError::InsufficientFunds => "insufficient_funds",
+            ValidationError::AccountExists => "account_exists",
+            ValidationError::DuplicateTransaction => "duplicate_transaction",
+            ValidationError::NodeInactive => "node_inactive",
+            ValidationError::Overflow => "overflow",
+            ValidationError::InvalidSymbol(_) => "invalid_symbol",
+            ValidationError::InvalidDecimals { .. } => "invalid_decimals",
+            ValidationError::AssetExists(_) => "asset_exists",
+            ValidationError::Unauthorized { .. } => "unauthorized",
+            ValidationError::InitialBalanceNotAllowed => "initial_balance_not_allowed",
+            ValidationError::WrongChain { .. } => "wrong_chain",
+            ValidationError::TransactionTooLarge { .. } => "transaction_too_large",
+            ValidationError::Unauthenticated => "unauthenticated",
+            ValidationError::InvalidToken => "invalid_token",
+            ValidationError::PermissionDenied { .. } => "permission_denied",
+            ValidationError::AccountNotBound(_) => "account_not_bound",
+            ValidationError::InvalidRewind { .. } => "invalid_rewind",
+        }
+    }
+
+    /// Parses a numeric command argument, naming the field on failure.
+    pub fn parse_number<T>(field: &'static str, value: &str) -> Result<T, ValidationError>
+    where
+        T: std::str::FromStr<Err = ParseIntError>,
+    {
+        value
+            .parse()
+            .map_err(|source| ValidationError::InvalidNumber {
+                field,
+                value: value.to_string(),
+                source,
+            })
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum NotFoundError {
+    #[error("Account '{0}' not found")]
+    Account(String),
+    #[error("Block '{0}' not found")]
+    Block(String),
+    #[error("Transaction '{0}' not found")]
+    Transaction(String),
+    #[error("Asset '{0}' not found")]
+    Asset(String),
+}
+
+impl NotFoundError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            NotFoundError::Account(_) => "account_not_found",
+            NotFoundError::Block(_) => "block_not_found",
+            NotFoundError::Transaction(_) => "transaction_not_found",
+            NotFoundError::Asset(_) => "asset_not_found",
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("Failed to access '{}'", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to encode or decode storage")]
+    Serialization(#[from] serde_json::Error),
+    #[error("Failed to decode stored data")]
+    Decode(#[from] DecodeError),
+    #[error("Storage format version {found} is newer than the supported version {supported}")]
+    UnsupportedVersion { found: u64, supported: u64 },
+    #[error("No undo data for block {0}")]
+    MissingUndo(u64),
+    #[error("The body of block {0} was pruned")]
+    Pruned(u64),
+    #[error("Key file '{}' is accessible to other users, restrict it to its owner", .0.display())]
+    KeyExposed(PathBuf),
+}
+
+impl StorageError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            StorageError::Io { .. } => "storage_io",
+            StorageError::Serialization(_) => "storage_serialization",
+            StorageError::Decode(_) => "storage_decode",
+            StorageError::UnsupportedVersion { .. } => "storage_version",
+            StorageError::MissingUndo(_) => "undo_missing",
+            StorageError::Pruned(_) => "block_pruned",
+            StorageError::KeyExposed(_) => "key_exposed",
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum NetworkError {
+    #[error("Failed to bind to {addr}")]
+    Bind {
+        addr: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Connection error")]
+    Io(#[from] io::Error),
+    #[error("Malformed message")]
+    Malformed(#[from] DecodeError),
+    #[error("Unexpected message")]
+    UnexpectedMessage,
+    #[error("Node answered [{code}]: {message}")]
+    Remote { code: String, message: String },
+    #[error("Gave up after {0:?}")]
+    Timeout(Duration),
+    #[error("Encrypted session failed")]
+    Handshake(#[from] snow::Error),
+    #[error("Node key is {actual}, expected {expected}")]
+    KeyMismatch { expected: String, actual: String },
+    #[error("This node only accepts encrypted sessions")]
+    EncryptionRequired,
+    #[error("Invalid peer '{0}', expected an address or address@<public key>")]
+    InvalidPeer(String),
+    #[error("Peer '{0}' has no pinned key, the secure profile needs address@<public key>")]
+    UnpinnedPeer(String),
+    #[error("Too many requests, slow down")]
+    RateLimited,
+    #[error("Node is serving too many connections")]
+    TooManyConnections,
+    #[error("Request exceeds {max} bytes")]
+    RequestTooLarge { max: usize },
+}
+
+impl NetworkError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            NetworkError::Bind { .. } => "network_bind",
+            NetworkError::Io(_) => "network_io",
+            NetworkError::Malformed(_) => "malformed_message",
+            NetworkError::UnexpectedMessage => "unexpected_message",
+            NetworkError::Remote { .. } => "remote_error",
+            NetworkError::Timeout(_) => "timeout",
+            NetworkError::Handshake(_) => "handshake_failed",
+            NetworkError::KeyMismatch { .. } => "node_key_mismatch",
+            NetworkError::EncryptionRequired => "encryption_required",
+            NetworkError::InvalidPeer(_) => "invalid_peer",
+            NetworkError::UnpinnedPeer(_) => "unpinned_peer",
+            NetworkError::RateLimited => "rate_limited",
+            NetworkError::TooManyConnections => "too_many_connections",
+            NetworkError::RequestTooLarge { .. } => "request_too_large",
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ConsensusError {
+    #[error("Invalid nonce {actual}, expected {expected}")]
+    InvalidNonce { expected: u64, actual: u64 },
+    #[error("{asset} balances add up to {balances}, but {minted} was minted and {burned} burned")]
+    SupplyMismatch {
+        asset: String,
+        minted: u64,
+        burned: u64,
+        balances: u128,
+    },
+    #[error("Peer {peer} has genesis {actual}, expected {expected}")]
+    GenesisMismatch {
+        peer: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("Block builds on {actual}, expected {expected}")]
+    InvalidParent { expected: String, actual: String },
+    #[error("Block has {count} transactions, the limit is {max}")]
+    TooManyTransactions { count: usize, max: usize },
+    #[error("Block transactions take {bytes} bytes, the limit is {max}")]
+    BlockTooLarge { bytes: usize, max: usize },
+    #[error("Block {height} is {actual}, but the checkpoint says {expected}")]
+    CheckpointMismatch {
+        height: u64,
+        expected: String,
+        actual: String,
+    },
+    #[error("Cannot rewind to height {height}, block {finalized} is final")]
+    BelowFinality { height: u64, finalized: u64 },
+    #[error("Replaying block {0} did not reproduce its receipts")]
+    ReplayMismatch(u64),
+}
+
+impl ConsensusError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ConsensusError::InvalidNonce { .. } => "invalid_nonce",
+            ConsensusError::SupplyMismatch { .. } => "supply_mismatch",
+            ConsensusError::GenesisMismatch { .. } => "genesis_mismatch",
+            ConsensusError::InvalidParent { .. } => "invalid_parent",
+            ConsensusError::TooManyTransactions { .. } => "too_many_transactions",
+            ConsensusError::BlockTooLarge { .. } => "block_too_large",
+            ConsensusError::CheckpointMismatch { .. } => "checkpoint_mismatch",
+            ConsensusError::BelowFinality { .. } => "below_finality",
+            ConsensusError::ReplayMismatch(_) => "replay_mismatch",
+        }
+    }
+}
+
+/// Failure to read data in the binary encoding.
+#[derive(Debug, Error, PartialEq)]
+pub enum DecodeError {
+    #[error("Unsupported encoding version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Unexpected end of input")]
+    UnexpectedEnd,
+    #[error("Invalid {kind} tag {tag}")]
+    InvalidTag { kind: &'static str, tag: u8 },
+    #[error("Invalid UTF-8 in string")]
+    InvalidUtf8,
+    #[error("{0} unexpected trailing bytes")]
+    TrailingBytes(usize),
+}
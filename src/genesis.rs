@@ -0,0 +1,129 @@
+use crate::encoding;
+use crate::error::{Result, StorageError, ValidationError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Chain id used when the genesis file does not set one.
+pub const DEV_CHAIN_ID: &str = "b-dev";
+
+/// Consensus rules every node of a network must agree on.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ConsensusParams {
+    /// Target interval between blocks.
+    pub block_time_secs: u64,
+    /// Maximum encoded size of the transactions in a block, in bytes.
+    #[serde(default = "default_max_block_bytes")]
+    pub max_block_bytes: usize,
+    /// Maximum number of transactions in a block.
+    #[serde(default = "default_max_block_transactions")]
+    pub max_block_transactions: usize,
+    /// Blocks with this many blocks on top of them are final.
+    #[serde(default)]
+    pub finality_depth: Option<u64>,
+}
+
+impl Default for ConsensusParams {
+    fn default() -> Self {
+        ConsensusParams {
+            block_time_secs: 10,
+            max_block_bytes: default_max_block_bytes(),
+            max_block_transactions: default_max_block_transactions(),
+            finality_depth: None,
+        }
+    }
+}
+
+fn default_max_block_bytes() -> usize {
+    1_000_000
+}
+
+fn default_max_block_transactions() -> usize {
+    1_000
+}
+
+/// Network configuration fixed at genesis, usually read from a genesis file.
+/// Every node started from the same configuration derives the same genesis block.
+///
+/// A network with a mint `authority` is closed: accounts start empty and native
+/// coin only enters the ledger through the genesis allocations and `Mint`
+/// transactions signed off by the authority. Without an authority (the default,
+/// meant for local development) `create-account` may still fund new accounts.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GenesisConfig {
+    #[serde(default = "dev_chain_id")]
+    pub chain_id: String,
+    /// Timestamp of the genesis block, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub timestamp: u64,
+    #[serde(default)]
+    pub consensus: ConsensusParams,
+    /// Account allowed to mint and burn the native coin.
+    #[serde(default)]
+    pub authority: Option<String>,
+    /// Initial native coin balances.
+    #[serde(default)]
+    pub allocations: BTreeMap<String, u64>,
+}
+
+impl Default for GenesisConfig {
+    fn default() -> Self {
+        GenesisConfig {
+            chain_id: dev_chain_id(),
+            timestamp: 0,
+            consensus: ConsensusParams::default(),
+            authority: None,
+            allocations: BTreeMap::new(),
+        }
+    }
+}
+
+impl GenesisConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path.as_ref()).map_err(|source| StorageError::Io {
+            path: path.as_ref().to_path_buf(),
+            source,
+        })?;
+        let config: GenesisConfig =
+            serde_json::from_reader(BufReader::new(file)).map_err(StorageError::from)?;
+        if config.initial_supply().is_none() {
+            return Err(ValidationError::Overflow.into());
+        }
+        Ok(config)
+    }
+
+    /// Sum of the allocations, or `None` if it does not fit in a `u64`.
+    pub fn initial_supply(&self) -> Option<u64> {
+        self.allocations
+            .values()
+            .try_fold(0u64, |total, &amount| total.checked_add(amount))
+    }
+
+    /// Hash of the configuration's canonical encoding, committed to by the
+    /// genesis block.
+    pub fn hash(&self) -> String {
+        format!("{:x}", Sha256::digest(encoding::encode(self)))
+    }
+
+    /// Whether `create-account` may credit new accounts out of thin air.
+    pub fn allows_free_balances(&self) -> bool {
+        self.authority.is_none()
+    }
+}
+
+fn dev_chain_id() -> String {
+    DEV_CHAIN_ID.to_string()
+}
+
+/// Reads a checkpoints file: a JSON object of trusted block hashes by height,
+/// such as `{"100": "3f2a..."}`.
+pub fn load_checkpoints<P: AsRef<Path>>(path: P) -> Result<BTreeMap<u64, String>> {
+    let file = File::open(path.as_ref()).map_err(|source| StorageError::Io {
+        path: path.as_ref().to_path_buf(),
+        source,
+    })?;
+    Ok(serde_json::from_reader(BufReader::new(file)).map_err(StorageError::from)?)
+}
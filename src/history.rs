@@ -0,0 +1,62 @@
+use crate::asset;
+use serde::{Deserialize, Serialize};
+
+/// Default number of entries returned per page by `history`.
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Direction {
+    Created,
+    Issued,
+    Minted,
+    Burned,
+    Sent,
+    Received,
+}
+
+/// A transaction that changed an account's balance, as recorded in the history index.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    pub height: u64,
+    pub tx_index: usize,
+    pub direction: Direction,
+    #[serde(default = "asset::native_asset")]
+    pub asset: String,
+    pub counterparty: Option<String>,
+    pub amount: u64,
+    /// Balance of the account right after the transaction was applied.
+    pub balance: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HistoryPage {
+    pub account: String,
+    /// 1-based page number.
+    pub page: usize,
+    pub page_size: usize,
+    pub total: usize,
+    /// Entries on this page, newest first.
+    pub entries: Vec<HistoryEntry>,
+}
+
+impl HistoryPage {
+    /// Slices a page out of `entries`, which are stored oldest first.
+    pub fn new(account: &str, entries: &[HistoryEntry], page: usize, page_size: usize) -> Self {
+        let total = entries.len();
+        let entries = entries
+            .iter()
+            .rev()
+            .skip(page.saturating_sub(1) * page_size)
+            .take(page_size)
+            .cloned()
+            .collect();
+        HistoryPage {
+            account: account.to_string(),
+            page,
+            page_size,
+            total,
+            entries,
+        }
+    }
+}
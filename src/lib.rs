@@ -1,46 +1,333 @@
 // use std::collections::HashMap;
+use crate::asset::{Asset, MAX_DECIMALS, NATIVE_ASSET};
+use crate::auth::{AuthConfig, Grant, Scope};
+use crate::error::{
+    ConsensusError, Error, NetworkError, NotFoundError, Result, StorageError, ValidationError,
+};
+use crate::genesis::GenesisConfig;
+use crate::history::{Direction, HistoryEntry, HistoryPage};
+use crate::limits::{ConnectionLimits, DeadlineStream, RateLimiter};
+use crate::message::Message;
+use crate::metrics::Metrics;
+use crate::receipt::{Receipt, ReceiptStatus, TxStatus};
+use crate::retention::{Retention, SNAPSHOTS_KEPT};
 use crate::storage::Storage;
+use crate::transport::{SecureStream, StaticKey};
+use crate::undo::BlockUndo;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
 use std::io::{BufRead, Write};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
+pub mod asset;
+pub mod auth;
+pub mod client;
+pub mod encoding;
+pub mod error;
+pub mod genesis;
+pub mod history;
+pub mod limits;
+pub mod message;
+pub mod metrics;
+pub mod migration;
+pub mod receipt;
+pub mod repl;
+pub mod retention;
+pub mod script;
 pub mod storage;
+pub mod transport;
+pub mod undo;
 use std::io::Read;
-use std::net::{TcpListener, TcpStream};
+use std::net::{SocketAddr, TcpListener, TcpStream};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::{debug, error, info, info_span, warn};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+/// How long a refused client has to show whether it speaks text or binary.
+const REFUSAL_PEEK_TIMEOUT: Duration = Duration::from_millis(200);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum Transaction {
     Transfer {
+        /// Network the transaction is meant for, so it cannot be replayed on another.
+        chain_id: String,
         from: String,
         to: String,
         amount: u64,
+        /// Per-sender sequence number, so that identical transfers get distinct ids.
+        nonce: u64,
     },
     CreateAccount {
+        chain_id: String,
         id: String,
         balance: u64,
     },
+    /// Creates a new asset and credits its whole initial supply to the issuer.
+    IssueAsset {
+        chain_id: String,
+        issuer: String,
+        symbol: String,
+        decimals: u8,
+        supply: u64,
+    },
+    /// Transfers any asset, the native coin included.
+    TransferAsset {
+        chain_id: String,
+        from: String,
+        to: String,
+        asset: String,
+        amount: u64,
+        nonce: u64,
+    },
+    /// Creates new units of an asset. Only its issuer, or the genesis authority
+    /// for the native coin, may mint.
+    Mint {
+        chain_id: String,
+        authority: String,
+        to: String,
+        asset: String,
+        amount: u64,
+        nonce: u64,
+    },
+    /// Destroys units of an asset held by its authority.
+    Burn {
+        chain_id: String,
+        authority: String,
+        asset: String,
+        amount: u64,
+        nonce: u64,
+    },
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+impl Transaction {
+    /// Content hash of the transaction, hex encoded. This is the transaction id.
+    pub fn hash(&self) -> String {
+        format!("{:x}", Sha256::digest(encoding::encode(self)))
+    }
+
+    /// Encoded size in bytes, counted against the block size limit.
+    pub fn size(&self) -> usize {
+        encoding::encode(self).len()
+    }
+
+    /// Accounts whose entries the transaction may change.
+    pub fn accounts(&self) -> Vec<&str> {
+        match self {
+            Transaction::Transfer { from, to, .. }
+            | Transaction::TransferAsset { from, to, .. } => {
+                vec![from, to]
+            }
+            Transaction::CreateAccount { id, .. } => vec![id],
+            Transaction::IssueAsset { issuer, .. } => vec![issuer],
+            Transaction::Mint { authority, to, .. } => vec![authority, to],
+            Transaction::Burn { authority, .. } => vec![authority],
+        }
+    }
+
+    /// Symbol of the asset the transaction moves, creates or changes, if not
+    /// only the native coin.
+    pub fn asset(&self) -> Option<&str> {
+        match self {
+            Transaction::Transfer { .. } | Transaction::CreateAccount { .. } => None,
+            Transaction::IssueAsset { symbol, .. } => Some(symbol),
+            Transaction::TransferAsset { asset, .. }
+            | Transaction::Mint { asset, .. }
+            | Transaction::Burn { asset, .. } => Some(asset),
+        }
+    }
+
+    /// Authority the transaction acts as, for mints and burns.
+    pub fn authority(&self) -> Option<&str> {
+        match self {
+            Transaction::Mint { authority, .. } | Transaction::Burn { authority, .. } => {
+                Some(authority)
+            }
+            _ => None,
+        }
+    }
+
+    /// Account whose nonce this transaction consumes, if any.
+    pub fn sender(&self) -> Option<&str> {
+        match self {
+            Transaction::Transfer { from, .. } | Transaction::TransferAsset { from, .. } => {
+                Some(from)
+            }
+            Transaction::Mint { authority, .. } | Transaction::Burn { authority, .. } => {
+                Some(authority)
+            }
+            Transaction::CreateAccount { .. } | Transaction::IssueAsset { .. } => None,
+        }
+    }
+
+    /// Nonce the transaction consumes from its sender, if any.
+    pub fn nonce(&self) -> Option<u64> {
+        match self {
+            Transaction::Transfer { nonce, .. }
+            | Transaction::TransferAsset { nonce, .. }
+            | Transaction::Mint { nonce, .. }
+            | Transaction::Burn { nonce, .. } => Some(*nonce),
+            Transaction::CreateAccount { .. } | Transaction::IssueAsset { .. } => None,
+        }
+    }
+
+    /// Chain the transaction was created for.
+    pub fn chain_id(&self) -> &str {
+        match self {
+            Transaction::Transfer { chain_id, .. }
+            | Transaction::CreateAccount { chain_id, .. }
+            | Transaction::IssueAsset { chain_id, .. }
+            | Transaction::TransferAsset { chain_id, .. }
+            | Transaction::Mint { chain_id, .. }
+            | Transaction::Burn { chain_id, .. } => chain_id,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Block {
+    chain_id: String,
     timestamp: u64,
     transactions: Vec<Transaction>,
+    receipts: Vec<Receipt>,
     prev_block_hash: String,
     nonce: u64,
+    /// Hash of the block, kept once its transactions and receipts are pruned.
+    #[serde(skip)]
+    pruned: Option<String>,
+}
+
+/// What a pruned node keeps of an old block.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BlockHeader {
+    pub chain_id: String,
+    pub timestamp: u64,
+    pub prev_block_hash: String,
+    pub nonce: u64,
+    /// Hash of the whole block, which the header alone no longer determines.
+    pub hash: String,
 }
 
 impl Block {
-    fn new(transactions: Vec<Transaction>, prev_block_hash: String) -> Self {
+    fn new(
+        chain_id: String,
+        timestamp: u64,
+        transactions: Vec<Transaction>,
+        receipts: Vec<Receipt>,
+        prev_block_hash: String,
+    ) -> Self {
         Block {
-            timestamp: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("Time went backwards")
-                .as_secs(),
+            chain_id,
+            timestamp,
             transactions,
+            receipts,
             prev_block_hash,
             nonce: 0,
+            pruned: None,
+        }
+    }
+
+    /// The first block of the chain described by `config`. It only depends on
+    /// the configuration, and commits to all of it through its parent hash.
+    fn genesis(config: &GenesisConfig) -> Self {
+        Block {
+            chain_id: config.chain_id.clone(),
+            timestamp: config.timestamp,
+            transactions: vec![],
+            receipts: vec![],
+            prev_block_hash: config.hash(),
+            nonce: 0,
+            pruned: None,
         }
     }
+
+    /// Hash of the block header and body, hex encoded.
+    pub fn hash(&self) -> String {
+        match &self.pruned {
+            Some(hash) => hash.clone(),
+            None => format!("{:x}", Sha256::digest(encoding::encode(self))),
+        }
+    }
+
+    /// Whether the block's transactions and receipts were pruned.
+    pub fn is_pruned(&self) -> bool {
+        self.pruned.is_some()
+    }
+
+    /// Drops the block's transactions and receipts, keeping its hash.
+    fn prune(&mut self) {
+        if self.pruned.is_none() {
+            self.pruned = Some(self.hash());
+            self.transactions = Vec::new();
+            self.receipts = Vec::new();
+        }
+    }
+
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            chain_id: self.chain_id.clone(),
+            timestamp: self.timestamp,
+            prev_block_hash: self.prev_block_hash.clone(),
+            nonce: self.nonce,
+            hash: self.hash(),
+        }
+    }
+
+    /// A pruned block restored from its header.
+    fn from_header(header: BlockHeader) -> Self {
+        Block {
+            chain_id: header.chain_id,
+            timestamp: header.timestamp,
+            transactions: vec![],
+            receipts: vec![],
+            prev_block_hash: header.prev_block_hash,
+            nonce: header.nonce,
+            pruned: Some(header.hash),
+        }
+    }
+
+    pub fn chain_id(&self) -> &str {
+        &self.chain_id
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    /// Receipts for the block's transactions, in the same order.
+    pub fn receipts(&self) -> &[Receipt] {
+        &self.receipts
+    }
+
+    pub fn prev_block_hash(&self) -> &str {
+        &self.prev_block_hash
+    }
+
+    fn to_json(&self, height: u64) -> serde_json::Value {
+        json!({
+            "height": height,
+            "hash": self.hash(),
+            "chain_id": self.chain_id,
+            "prev_block_hash": self.prev_block_hash,
+            "timestamp": self.timestamp,
+            "nonce": self.nonce,
+            "pruned": self.is_pruned(),
+            "transactions": self
+                .transactions
+                .iter()
+                .zip(&self.receipts)
+                .map(|(tx, receipt)| json!({
+                    "hash": tx.hash(),
+                    "transaction": tx,
+                    "receipt": receipt.status,
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
 }
 
 pub struct Blockchain {
@@ -49,39 +336,228 @@ pub struct Blockchain {
     pub block_time: Duration,
     pub chain: Vec<Block>,
     pub is_active: bool,
+    pub metrics: Arc<Metrics>,
+    /// Local port serving Prometheus metrics once the node is started, if any.
+    pub metrics_port: Option<u16>,
+    pub genesis: GenesisConfig,
+    /// Address the node listens on for commands.
+    pub listen_addr: String,
+    /// Nodes of the same network, whose genesis is checked on start.
+    pub peers: Vec<String>,
+    /// Added to the wall clock when stamping blocks, so simulations can skip ahead.
+    pub clock_offset: Duration,
+    /// Tokens accepted on the command port. Every session is trusted when unset.
+    pub auth: Option<AuthConfig>,
+    /// Static key for encrypted sessions, also presented to peers. Only
+    /// plaintext sessions are served when unset.
+    pub node_key: Option<StaticKey>,
+    /// Refuses plaintext sessions, as in the secure profile.
+    pub require_encryption: bool,
+    /// Limits on connections and requests to the node's port.
+    pub limits: ConnectionLimits,
+    /// Block hashes this node trusts, by height. A checkpointed block and its
+    /// ancestors are final.
+    pub checkpoints: BTreeMap<u64, String>,
+    /// Snapshots to write and old blocks to prune.
+    pub retention: Retention,
+    rate_limiter: RateLimiter,
 }
 
 impl Blockchain {
+    /// Builds a dev chain with in-memory storage. Use `with_genesis` with
+    /// `Storage::open` for a chain backed by files.
     pub fn new(block_time: Duration) -> Self {
-        let genesis_block = Block::new(vec![], "".to_string()); // Create the genesis block with no transactions
-        Blockchain {
-            storage: Storage::load().unwrap_or_default(),
+        let mut genesis = GenesisConfig::default();
+        genesis.consensus.block_time_secs = block_time.as_secs();
+        let mut blockchain = Self::with_genesis(genesis, Storage::default());
+        // The genesis only records whole seconds
+        blockchain.block_time = block_time;
+        blockchain
+    }
+
+    /// Builds a chain for the network described by `genesis`. Genesis
+    /// allocations are credited when `storage` holds no ledger yet.
+    pub fn with_genesis(genesis: GenesisConfig, storage: Storage) -> Self {
+        let genesis_block = Block::genesis(&genesis);
+        let mut blockchain = Blockchain {
+            storage,
             pending_transactions: Vec::new(),
-            block_time,
+            block_time: Duration::from_secs(genesis.consensus.block_time_secs),
             chain: vec![genesis_block],
             is_active: false,
+            metrics: Arc::new(Metrics::default()),
+            metrics_port: None,
+            genesis,
+            listen_addr: client::DEFAULT_ADDR.to_string(),
+            peers: Vec::new(),
+            clock_offset: Duration::ZERO,
+            auth: None,
+            node_key: None,
+            require_encryption: false,
+            limits: ConnectionLimits::default(),
+            checkpoints: BTreeMap::new(),
+            retention: Retention::default(),
+            rate_limiter: RateLimiter::default(),
+        };
+        if blockchain.storage.accounts.is_empty() && blockchain.storage.minted == 0 {
+            blockchain.apply_genesis_allocations();
+        }
+        blockchain.load_blocks();
+        blockchain.metrics.set_chain_height(blockchain.height());
+        blockchain
+    }
+
+    /// Restores the blocks mined before a restart: the headers of pruned
+    /// blocks, then the block log, stopping at the first block that does not
+    /// extend the chain. Blocks the ledger has not applied yet, as after it was
+    /// restored from a snapshot, are replayed.
+    fn load_blocks(&mut self) {
+        let headers = match self.storage.load_headers() {
+            Ok(headers) => headers,
+            Err(e) => {
+                warn!(error = %e, "Failed to load the pruned block headers");
+                return;
+            }
+        };
+        for header in headers {
+            if header.prev_block_hash != self.get_last_block_hash() {
+                warn!(
+                    height = self.chain.len(),
+                    "Ignoring the rest of the pruned headers"
+                );
+                return;
+            }
+            self.chain.push(Block::from_header(header));
+        }
+        let blocks = match self.storage.load_blocks() {
+            Ok(blocks) => blocks,
+            Err(e) => {
+                warn!(error = %e, "Failed to load the block log");
+                return;
+            }
+        };
+        // Blocks pruned just before a restart may still be in the log
+        let pruned: HashSet<String> = self.chain.iter().map(Block::hash).collect();
+        let applied = self.storage.height;
+        for block in blocks {
+            if pruned.contains(&block.hash()) {
+                continue;
+            }
+            if let Err(e) = self.validate_block(&block) {
+                warn!(height = self.chain.len(), error = %e, "Ignoring the rest of the block log");
+                break;
+            }
+            let height = self.chain.len() as u64;
+            if self.storage.height.is_some_and(|applied| applied < height) {
+                if let Err(e) = self.replay_block(height, &block) {
+                    warn!(height, error = %e, "Ignoring the rest of the block log");
+                    break;
+                }
+            }
+            self.chain.push(block);
+        }
+        if self.storage.height != applied {
+            info!(
+                from = applied,
+                to = self.storage.height,
+                "Replayed blocks missing from the ledger"
+            );
+            if let Err(e) = self.storage.save() {
+                warn!(error = %e, "Failed to save storage");
+            }
         }
     }
 
-    pub fn start_node(blockchain: Arc<Mutex<Blockchain>>) {
-        let listener = TcpListener::bind("127.0.0.1:3000").unwrap();
-        println!("Starting blockchain node on TCP port 3000...");
+    /// Applies a block from the block log to a ledger that is behind it.
+    fn replay_block(&mut self, height: u64, block: &Block) -> Result<()> {
+        let applied = self.storage.height.unwrap_or_default();
+        if applied + 1 < height {
+            return Err(StorageError::Pruned(applied + 1).into());
+        }
+        let receipts = self.apply_block(height, &block.transactions);
+        if receipts != block.receipts {
+            let undo = self.storage.undo.remove(&height);
+            undo.ok_or(StorageError::MissingUndo(height))?
+                .restore(&mut self.storage);
+            self.storage.height = Some(applied);
+            return Err(ConsensusError::ReplayMismatch(height).into());
+        }
+        Ok(())
+    }
+
+    fn apply_genesis_allocations(&mut self) {
+        for (account, &amount) in &self.genesis.allocations {
+            self.storage.accounts.insert(account.clone(), amount);
+            self.storage.minted += amount;
+            self.storage
+                .history
+                .entry(account.clone())
+                .or_default()
+                .push(HistoryEntry {
+                    height: 0,
+                    tx_index: 0,
+                    direction: Direction::Created,
+                    asset: NATIVE_ASSET.to_string(),
+                    counterparty: None,
+                    amount,
+                    balance: amount,
+                });
+        }
+        if let Some(authority) = &self.genesis.authority {
+            self.storage.accounts.entry(authority.clone()).or_insert(0);
+        }
+    }
+
+    /// Starts serving commands and mining. Refuses to start if a reachable
+    /// peer runs a different genesis. Returns the address the node listens on.
+    pub fn start_node(blockchain: Arc<Mutex<Blockchain>>) -> Result<SocketAddr> {
+        let (addr, peers, genesis_hash, node_key, require_encryption) = {
+            let bc = blockchain.lock().unwrap();
+            let node_key = bc.node_key.clone();
+            (
+                bc.listen_addr.clone(),
+                bc.peers.clone(),
+                bc.genesis_hash(),
+                node_key,
+                bc.require_encryption,
+            )
+        };
+        for peer in &peers {
+            Self::verify_peer_genesis(peer, &genesis_hash, node_key.as_ref(), require_encryption)?;
+        }
+
+        let listener = TcpListener::bind(&addr).map_err(|source| NetworkError::Bind {
+            addr: addr.clone(),
+            source,
+        })?;
+        let local_addr = listener.local_addr().map_err(NetworkError::from)?;
+        info!(addr = %local_addr, "Starting blockchain node");
 
         // Set the node to active when starting
         {
             let mut bc = blockchain.lock().unwrap();
             bc.is_active = true;
+            if let Some(port) = bc.metrics_port {
+                metrics::serve(port, bc.metrics.clone())?;
+            }
         }
 
+        // Copied once, so accepting never waits for the chain's lock
+        let (metrics, max_connections) = {
+            let bc = blockchain.lock().unwrap();
+            (bc.metrics.clone(), bc.limits.max_connections)
+        };
         let blockchain_clone1 = blockchain.clone();
         std::thread::spawn(move || {
             for stream in listener.incoming() {
                 match stream {
-                    Ok(stream) => {
-                        let blockchain_clone = blockchain_clone1.clone();
-                        Self::handle_connection(stream, blockchain_clone);
-                    }
-                    Err(_e) => { /* handle error */ }
+                    Ok(stream) => Self::accept_connection(
+                        stream,
+                        blockchain_clone1.clone(),
+                        &metrics,
+                        max_connections,
+                    ),
+                    Err(e) => warn!(error = %e, "Failed to accept connection"),
                 }
             }
         });
@@ -105,166 +581,1381 @@ impl Blockchain {
                 std::thread::sleep(block_time);
             }
         });
+        Ok(local_addr)
+    }
+
+    /// Compares the genesis hash reported by `peer` with ours. Unreachable
+    /// peers are skipped, since they cannot be checked. A peer given as
+    /// `address@<public key>` must present that key in an encrypted session;
+    /// others are reached encrypted when this node has a key. With
+    /// `require_pinned`, as in the secure profile, every peer must be pinned.
+    fn verify_peer_genesis(
+        peer: &str,
+        genesis_hash: &str,
+        node_key: Option<&StaticKey>,
+        require_pinned: bool,
+    ) -> Result<()> {
+        let (peer, pinned_key) = match peer.split_once('@') {
+            Some((addr, key)) => {
+                let key = transport::from_hex(key)
+                    .ok_or_else(|| NetworkError::InvalidPeer(peer.to_string()))?;
+                (addr, Some(key))
+            }
+            None if require_pinned => {
+                return Err(NetworkError::UnpinnedPeer(peer.to_string()).into())
+            }
+            None => (peer, None),
+        };
+        let encryption = match (node_key, pinned_key) {
+            (None, None) => None,
+            (local, pinned) => Some(client::Encryption {
+                key: local.cloned().unwrap_or_else(StaticKey::generate),
+                node_key: pinned,
+            }),
+        };
+        let session = client::Session {
+            token: None,
+            encryption,
+        };
+        let response = match client::send_message_as(peer, &session, &Message::GetChainInfo) {
+            Ok(response) => response,
+            Err(NetworkError::Io(e)) => {
+                warn!(%peer, error = %e, "Skipping genesis check of unreachable peer");
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let Message::ChainInfo {
+            genesis_hash: peer_hash,
+            ..
+        } = response
+        else {
+            return Err(NetworkError::UnexpectedMessage.into());
+        };
+        if peer_hash != genesis_hash {
+            return Err(ConsensusError::GenesisMismatch {
+                peer: peer.to_string(),
+                expected: genesis_hash.to_string(),
+                actual: peer_hash,
+            }
+            .into());
+        }
+        debug!(%peer, "Peer genesis matches");
+        Ok(())
+    }
+
+    /// Hash of the genesis block, identical on every node of the network.
+    pub fn genesis_hash(&self) -> String {
+        self.chain[0].hash()
+    }
+    /// Serves `stream` on its own thread, or refuses it when the node is
+    /// already serving as many connections as it allows.
+    fn accept_connection(
+        stream: TcpStream,
+        blockchain: Arc<Mutex<Blockchain>>,
+        metrics: &Arc<Metrics>,
+        max_connections: u64,
+    ) {
+        if metrics.connections_active() >= max_connections {
+            let metrics = metrics.clone();
+            std::thread::spawn(move || Self::refuse_connection(stream, &metrics));
+            return;
+        }
+        let metrics = metrics.clone();
+        metrics.connection_opened();
+        std::thread::spawn(move || {
+            Self::handle_connection(stream, &blockchain);
+            metrics.connection_closed();
+        });
+    }
+
+    fn handle_connection(stream: TcpStream, blockchain: &Mutex<Blockchain>) {
+        let peer = stream
+            .peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default();
+        let span = info_span!("connection", %peer);
+        let _enter = span.enter();
+        Self::serve_connection(stream, blockchain);
     }
-    fn handle_connection(mut stream: TcpStream, blockchain: Arc<Mutex<Blockchain>>) {
+
+    fn serve_connection(stream: TcpStream, blockchain: &Mutex<Blockchain>) {
+        let limits = blockchain.lock().unwrap().limits.clone();
+        // The whole request must arrive in time, however it is split
+        let deadline = Instant::now() + limits.read_timeout;
+        let client_ip = stream
+            .peer_addr()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_default();
+        if let Err(e) = stream.set_read_timeout(Some(limits.read_timeout)) {
+            warn!(error = %e, "Failed to set the read timeout");
+        }
+        let mut first = [0; 1];
+        if matches!(stream.peek(&mut first), Ok(1) if first[0] == transport::HANDSHAKE_TAG) {
+            Self::serve_encrypted(stream, blockchain, &limits, &client_ip, deadline);
+            return;
+        }
+        let mut stream = DeadlineStream::new(stream, deadline);
         let mut buffer = Vec::new();
-        let mut stream_clone = stream.try_clone().expect("Failed to clone stream");
 
-        match stream_clone.read_to_end(&mut buffer) {
-            Ok(size) => {
-                if size == 0 {
-                    println!("No data received, closing connection");
-                    return;
-                }
-                let command = String::from_utf8_lossy(&buffer).trim().to_string();
-                println!("Received command: '{}'", command);
-                let mut bc = blockchain.lock().unwrap();
-                match bc.process_command(&command) {
-                    Ok(message) => println!("{}", message),
-                    Err(error) => println!("Error: {}", error),
-                }
+        let read = (&mut stream)
+            .take(limits.max_request_bytes as u64 + 1)
+            .read_to_end(&mut buffer);
+        let response = match read {
+            Ok(0) => {
+                debug!("No data received, closing connection");
+                return;
+            }
+            Ok(size) if size > limits.max_request_bytes => {
+                let error = NetworkError::RequestTooLarge {
+                    max: limits.max_request_bytes,
+                };
+                Self::refuse(blockchain, &buffer, error.into())
+            }
+            Ok(_) => Self::respond(blockchain, &buffer, &client_ip, false),
+            Err(e) if is_timeout(&e) => {
+                let error = NetworkError::Timeout(limits.read_timeout);
+                Self::refuse(blockchain, &buffer, error.into())
             }
             Err(e) => {
-                println!(
-                    "An error occurred, terminating connection with {}: {}",
-                    stream.peer_addr().unwrap(),
-                    e
-                );
+                warn!(error = %e, "Terminating connection after read error");
                 return;
             }
+        };
+        if let Err(e) = stream.write_all(&response) {
+            warn!(error = %e, "Failed to send response");
+        }
+    }
+
+    fn serve_encrypted(
+        stream: TcpStream,
+        blockchain: &Mutex<Blockchain>,
+        limits: &ConnectionLimits,
+        client_ip: &str,
+        deadline: Instant,
+    ) {
+        let Some(key) = blockchain.lock().unwrap().node_key.clone() else {
+            warn!("Refusing encrypted session: this node has no key");
+            return;
+        };
+        let mut stream = DeadlineStream::new(stream, deadline);
+        let mut tag = [0; 1];
+        let session = stream
+            .read_exact(&mut tag)
+            .map_err(NetworkError::from)
+            .and_then(|()| SecureStream::accept(stream, &key))
+            .and_then(|mut stream| {
+                stream.limit_messages(limits.max_request_bytes);
+                Ok((stream.receive()?, stream))
+            });
+        let (request, mut stream) = match session {
+            Ok(session) => session,
+            Err(e) => {
+                let error = match e {
+                    NetworkError::Io(e) if is_timeout(&e) => {
+                        NetworkError::Timeout(limits.read_timeout)
+                    }
+                    e => e,
+                };
+                let error = Error::from(error);
+                warn!(code = error.code(), %error, "Encrypted session failed");
+                blockchain.lock().unwrap().metrics.record_refused(&error);
+                return;
+            }
+        };
+        debug!(client_key = %transport::to_hex(stream.remote_key()), "Encrypted session");
+        let response = Self::respond(blockchain, &request, client_ip, true);
+        if let Err(e) = stream.send(&response) {
+            warn!(error = %e, "Failed to send response");
+        }
+    }
+
+    /// Turns away a client while the node serves as many connections as it
+    /// may, answering in the format of the request it has begun to send.
+    fn refuse_connection(mut stream: TcpStream, metrics: &Metrics) {
+        let error = Error::from(NetworkError::TooManyConnections);
+        warn!(code = error.code(), "Refusing connection");
+        metrics.record_refused(&error);
+        let mut start = [0; 512];
+        let _ = stream.set_read_timeout(Some(REFUSAL_PEEK_TIMEOUT));
+        let peeked = stream.peek(&mut start).unwrap_or(0);
+        let _ = stream.write_all(&error_response(&start[..peeked], &error));
+    }
+
+    /// Answers a request that could not be served with `error`, in the
+    /// request's format.
+    fn refuse(blockchain: &Mutex<Blockchain>, request: &[u8], error: Error) -> Vec<u8> {
+        warn!(code = error.code(), %error, "Request refused");
+        blockchain.lock().unwrap().metrics.record_refused(&error);
+        error_response(request, &error)
+    }
+
+    /// Answers one request: a text command or a binary message, after an
+    /// optional auth header.
+    /// Rate limits apply per known auth token, or per IP for sessions without
+    /// one.
+    fn respond(
+        blockchain: &Mutex<Blockchain>,
+        request: &[u8],
+        client_ip: &str,
+        encrypted: bool,
+    ) -> Vec<u8> {
+        let (token, body) = auth::split_header(request);
+        let grant = {
+            let mut bc = blockchain.lock().unwrap();
+            let grant = bc.authorize(token);
+            // Only tokens the auth config knows get their own bucket, so
+            // made-up ones cannot dodge the limit of their IP
+            let client = match (&bc.auth, token) {
+                (Some(_), Some(token)) if grant.is_ok() => format!("token {}", token),
+                _ => format!("ip {}", client_ip),
+            };
+            let limits = bc.limits.clone();
+            if !bc.rate_limiter.allow(&client, &limits, Instant::now()) {
+                Err(NetworkError::RateLimited.into())
+            } else if bc.require_encryption && !encrypted {
+                Err(NetworkError::EncryptionRequired.into())
+            } else {
+                grant
+            }
+        };
+        let grant = match grant {
+            Ok(grant) => grant,
+            Err(error) => return Self::refuse(blockchain, request, error),
+        };
+        if Message::is_binary(body) {
+            let response = match encoding::decode(body) {
+                Ok(message) => blockchain
+                    .lock()
+                    .unwrap()
+                    .process_message_as(message, &grant),
+                Err(e) => {
+                    let error = Error::from(NetworkError::from(e));
+                    warn!(code = error.code(), %error, "Malformed message");
+                    Message::Error {
+                        code: error.code().to_string(),
+                        message: error.to_string(),
+                    }
+                }
+            };
+            return encoding::encode(&response);
+        }
+        let command = String::from_utf8_lossy(body).trim().to_string();
+        info!(%command, "Received command");
+        let response = {
+            let mut bc = blockchain.lock().unwrap();
+            match bc.process_command_as(&command, &grant) {
+                Ok(message) => message,
+                Err(error) => {
+                    warn!(code = error.code(), %error, "Command failed");
+                    error.to_wire()
+                }
+            }
+        };
+        debug!(%response, "Sending response");
+        response.into_bytes()
+    }
+
+    /// Grant of a session that presented `token`. Sessions are trusted on a
+    /// node without auth config.
+    pub fn authorize(&self, token: Option<&str>) -> Result<Grant> {
+        match &self.auth {
+            Some(auth) => auth.authorize(token),
+            None => Ok(Scope::Admin.into()),
+        }
+    }
+
+    /// Refuses a mint or burn unless the session may act as its authority.
+    fn check_acts_for(grant: &Grant, authority: Option<&str>) -> Result<()> {
+        match authority {
+            Some(authority) if !grant.may_act_for(authority) => {
+                Err(ValidationError::AccountNotBound(authority.to_string()).into())
+            }
+            _ => Ok(()),
         }
     }
 
     pub fn stop_node(&mut self) {
         self.is_active = false;
-        println!("Blockchain node stopped.");
+        info!("Blockchain node stopped");
     }
 
-    pub fn add_transaction(&mut self, from: String, to: String, amount: u64) -> Result<(), String> {
+    pub fn add_transaction(&mut self, from: String, to: String, amount: u64) -> Result<String> {
+        let transaction = self.check_add_transaction(from, to, amount);
+        self.admit(transaction)
+    }
+
+    fn check_add_transaction(&self, from: String, to: String, amount: u64) -> Result<Transaction> {
         // Check if the from account exists and has enough balance
         let from_balance = self.storage.accounts.get(&from).copied().unwrap_or(0);
         if from_balance < amount {
-            return Err("Insufficient balance".to_string());
+            return Err(ValidationError::InsufficientFunds.into());
         }
 
-        // Create the transaction and add it to the pending transactions list
-        let transaction = Transaction::Transfer { from, to, amount };
+        // Create the transaction to add to the pending transactions list
+        let nonce = self.next_nonce(&from);
+        Ok(Transaction::Transfer {
+            chain_id: self.genesis.chain_id.clone(),
+            from,
+            to,
+            amount,
+            nonce,
+        })
+    }
+
+    /// Admits a transaction built elsewhere, for example by a client holding
+    /// the sender's key. It must be meant for this chain.
+    pub fn submit_transaction(&mut self, transaction: Transaction) -> Result<String> {
+        let checked = self.check_submitted(transaction);
+        self.admit(checked)
+    }
+
+    fn check_submitted(&self, transaction: Transaction) -> Result<Transaction> {
+        if !self.is_active {
+            return Err(ValidationError::NodeInactive.into());
+        }
+        self.check_chain_id(transaction.chain_id())?;
+        let id = transaction.hash();
+        if self.pending_transactions.iter().any(|tx| tx.hash() == id) {
+            return Err(ValidationError::DuplicateTransaction.into());
+        }
+        // A nonce out of sequence would shift every later transaction from
+        // the same sender
+        if let (Some(sender), Some(nonce)) = (transaction.sender(), transaction.nonce()) {
+            let expected = self.next_nonce(sender);
+            if nonce != expected {
+                return Err(ConsensusError::InvalidNonce {
+                    expected,
+                    actual: nonce,
+                }
+                .into());
+            }
+        }
+        Ok(transaction)
+    }
+
+    fn check_chain_id(&self, chain_id: &str) -> Result<()> {
+        if chain_id != self.genesis.chain_id {
+            return Err(ValidationError::WrongChain {
+                expected: self.genesis.chain_id.clone(),
+                actual: chain_id.to_string(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Adds a validated transaction to the mempool and returns its id.
+    /// Rejected transactions are counted by reason.
+    fn admit(&mut self, transaction: Result<Transaction>) -> Result<String> {
+        let transaction = transaction
+            .and_then(|tx| self.check_size(tx))
+            .inspect_err(|e| self.metrics.record_rejected(e))?;
+        let id = transaction.hash();
         self.pending_transactions.push(transaction);
+        self.metrics.record_admitted();
+        self.metrics
+            .set_mempool_size(self.pending_transactions.len());
+        Ok(id)
+    }
+
+    /// Rejects transactions too large to ever fit in a block.
+    fn check_size(&self, transaction: Transaction) -> Result<Transaction> {
+        let size = transaction.size();
+        let max = self.genesis.consensus.max_block_bytes;
+        if size > max {
+            return Err(ValidationError::TransactionTooLarge { size, max }.into());
+        }
+        Ok(transaction)
+    }
+
+    /// Number of pending transactions, taken in order, that fit in the next
+    /// block. The rest stay in the mempool for the blocks after it.
+    fn block_capacity(&self) -> usize {
+        let consensus = &self.genesis.consensus;
+        let mut bytes = 0;
+        self.pending_transactions
+            .iter()
+            .take(consensus.max_block_transactions)
+            .take_while(|tx| {
+                bytes += tx.size();
+                bytes <= consensus.max_block_bytes
+            })
+            .count()
+    }
+
+    /// Checks a block received from a peer against the consensus rules
+    /// before it is accepted on top of the current chain.
+    pub fn validate_block(&self, block: &Block) -> Result<()> {
+        self.check_chain_id(block.chain_id())?;
+        let expected = self.get_last_block_hash();
+        if block.prev_block_hash() != expected {
+            return Err(ConsensusError::InvalidParent {
+                expected,
+                actual: block.prev_block_hash().to_string(),
+            }
+            .into());
+        }
+        let height = self.chain.len() as u64;
+        if let Some(expected) = self.checkpoints.get(&height) {
+            let actual = block.hash();
+            if actual != *expected {
+                return Err(ConsensusError::CheckpointMismatch {
+                    height,
+                    expected: expected.clone(),
+                    actual,
+                }
+                .into());
+            }
+        }
+        let consensus = &self.genesis.consensus;
+        let count = block.transactions().len();
+        if count > consensus.max_block_transactions {
+            return Err(ConsensusError::TooManyTransactions {
+                count,
+                max: consensus.max_block_transactions,
+            }
+            .into());
+        }
+        let bytes = block.transactions().iter().map(Transaction::size).sum();
+        if bytes > consensus.max_block_bytes {
+            return Err(ConsensusError::BlockTooLarge {
+                bytes,
+                max: consensus.max_block_bytes,
+            }
+            .into());
+        }
         Ok(())
     }
 
+    /// Nonce the next transfer from `account` must carry: one past the
+    /// highest nonce of its transfers still waiting in the mempool, or else
+    /// the confirmed nonce.
+    fn next_nonce(&self, account: &str) -> u64 {
+        let confirmed = self.storage.nonces.get(account).copied().unwrap_or(0);
+        self.pending_transactions
+            .iter()
+            .filter(|tx| tx.sender() == Some(account))
+            .filter_map(|tx| tx.nonce())
+            .map(|nonce| nonce + 1)
+            .fold(confirmed, u64::max)
+    }
+
     pub fn mine_block(&mut self) {
         if !self.is_active {
-            println!("Mining attempted while blockchain node is inactive.");
+            warn!("Mining attempted while blockchain node is inactive");
             return;
         }
 
         if self.pending_transactions.is_empty() {
-            println!("No transactions to mine, waiting for new transactions...");
+            debug!("No transactions to mine, waiting for new transactions");
             return;
         }
 
-        println!("Starting to mine a new block...");
-        let transactions = std::mem::take(&mut self.pending_transactions);
+        let height = self.chain.len() as u64;
+        let span = info_span!("block", height);
+        let _enter = span.enter();
+        let started = Instant::now();
+
+        let capacity = self.block_capacity();
+        debug!(
+            transactions = capacity,
+            carried_over = self.pending_transactions.len() - capacity,
+            "Starting to mine a new block"
+        );
+        let transactions: Vec<Transaction> = self.pending_transactions.drain(..capacity).collect();
         let prev_block_hash = self.get_last_block_hash();
+        let receipts = self.apply_block(height, &transactions);
+
+        let new_block = Block::new(
+            self.genesis.chain_id.clone(),
+            self.now(),
+            transactions,
+            receipts,
+            prev_block_hash,
+        );
+        let hash = new_block.hash();
+        let transaction_count = new_block.transactions.len();
+        self.chain.push(new_block);
+        self.metrics.record_block_mined(started.elapsed(), height);
+        self.metrics
+            .set_mempool_size(self.pending_transactions.len());
+
+        let write_started = Instant::now();
+        self.trim_undo();
+        if let Err(e) = self.storage.save() {
+            warn!(error = %e, "Failed to save storage");
+        }
+        if let Err(e) = self.storage.append_block(self.chain.last().unwrap()) {
+            warn!(error = %e, "Failed to append block to the block log");
+        }
+        if let Err(e) = self.apply_retention(height) {
+            warn!(error = %e, "Failed to snapshot or prune storage");
+        }
+        self.metrics.record_storage_write(write_started.elapsed());
 
-        for transaction in &transactions {
-            match transaction {
-                Transaction::CreateAccount { id, balance } => {
-                    println!("Processing create-account transaction for '{}'", id);
-                    // Insert only if the account does not already exist to prevent double processing
-                    self.storage.accounts.entry(id.clone()).or_insert(*balance);
+        if let Err(e) = self.check_invariants() {
+            error!(error = %e, "Ledger invariant violated");
+        }
+        info!(%hash, transactions = transaction_count, "Block mined");
+    }
+
+    /// Applies `transactions` as the block at `height`: updates the ledger,
+    /// indexes what was included and records undo data. Returns the receipts.
+    fn apply_block(&mut self, height: u64, transactions: &[Transaction]) -> Vec<Receipt> {
+        let undo = BlockUndo::capture(&self.storage, transactions);
+        let receipts = transactions
+            .iter()
+            .enumerate()
+            .map(|(tx_index, transaction)| {
+                let status = self.apply_transaction(transaction);
+                if status == ReceiptStatus::Included {
+                    self.index_transaction(height, tx_index, transaction);
                 }
-                Transaction::Transfer { from, to, amount } => {
-                    if let Some(sender_balance) = self.storage.accounts.get_mut(from) {
-                        if *sender_balance >= *amount {
-                            *sender_balance -= amount;
-                            let receiver_balance =
-                                self.storage.accounts.entry(to.clone()).or_insert(0);
-                            *receiver_balance += amount;
-                        } else {
-                            println!(
-                                "Insufficient funds for transfer from '{}' to '{}'",
-                                from, to
-                            );
-                        }
-                    } else {
-                        println!("Sender account '{}' not found", from);
-                    }
+                Receipt {
+                    tx_id: transaction.hash(),
+                    status,
                 }
+            })
+            .collect();
+        self.storage.undo.insert(height, undo);
+        self.storage.height = Some(height);
+        receipts
+    }
+
+    /// Drops the undo data of blocks the chain can no longer be rewound past.
+    fn trim_undo(&mut self) {
+        let floor = self
+            .retention
+            .undo_floor(self.height(), self.finalized_height());
+        self.storage.undo = self.storage.undo.split_off(&(floor + 1));
+    }
+
+    /// Writes a snapshot if one is due at `height`, then prunes the bodies
+    /// of blocks that left the retention window.
+    fn apply_retention(&mut self, height: u64) -> Result<()> {
+        if self.retention.is_snapshot_height(height) {
+            self.storage.write_snapshot(height)?;
+            let snapshots = self.storage.snapshots();
+            let stale = snapshots.len().saturating_sub(SNAPSHOTS_KEPT);
+            for &old in &snapshots[..stale] {
+                self.storage.remove_snapshot(old)?;
             }
+            info!(height, "Snapshot written");
         }
+        let latest_snapshot = self.storage.snapshots().last().copied();
+        self.prune_to(self.retention.prune_height(height, latest_snapshot))
+    }
 
-        let new_block = Block::new(transactions, prev_block_hash);
-        self.chain.push(new_block);
-        println!("Block mined successfully and added to the chain.");
+    /// Prunes the bodies and undo data of the blocks up to `height`.
+    fn prune_to(&mut self, height: u64) -> Result<()> {
+        let first = self.pruned_height() + 1;
+        if height < first {
+            return Ok(());
+        }
+        let mut headers = Vec::new();
+        for block_height in first..=height {
+            let block = &mut self.chain[block_height as usize];
+            block.prune();
+            headers.push(block.header());
+            self.storage.undo.remove(&block_height);
+        }
+        // Headers first: a restart in between finds the pruned blocks twice
+        // rather than not at all
+        self.storage.append_headers(&headers)?;
+        self.storage.rewrite_blocks(self.unpruned_blocks())?;
+        self.storage.save()?;
+        info!(from = first, to = height, "Pruned block bodies");
+        Ok(())
+    }
+
+    /// Height of the newest pruned block, or 0 if none was pruned.
+    pub fn pruned_height(&self) -> u64 {
+        self.chain[1..].partition_point(Block::is_pruned) as u64
+    }
+
+    /// Blocks above the pruned ones, which the block log holds.
+    fn unpruned_blocks(&self) -> &[Block] {
+        &self.chain[self.pruned_height() as usize + 1..]
+    }
+
+    /// Height of the newest final block: buried `finality_depth` blocks deep,
+    /// or at a checkpoint this chain matches. Genesis is always final.
+    pub fn finalized_height(&self) -> u64 {
+        let tip = self.height();
+        let by_depth = self
+            .genesis
+            .consensus
+            .finality_depth
+            .map_or(0, |depth| tip.saturating_sub(depth));
+        let by_checkpoint = self
+            .checkpoints
+            .range(..=tip)
+            .rev()
+            .find(|(&height, hash)| self.chain[height as usize].hash() == **hash)
+            .map_or(0, |(&height, _)| height);
+        by_depth.max(by_checkpoint)
+    }
+
+    pub fn is_final(&self, height: u64) -> bool {
+        height <= self.finalized_height()
+    }
+
+    /// Takes the blocks above `height` off the chain, restoring the ledger from
+    /// their undo data. Their transactions go back to the mempool, ahead of
+    /// the ones already pending. Returns how many went back.
+    pub fn rewind_to(&mut self, height: u64) -> Result<usize> {
+        let tip = self.height();
+        if height >= tip {
+            return Err(ValidationError::InvalidRewind { height, tip }.into());
+        }
+        // Final blocks are never taken back, so no reorg reaches below them
+        let finalized = self.finalized_height();
+        if height < finalized {
+            return Err(ConsensusError::BelowFinality { height, finalized }.into());
+        }
+        if height < self.pruned_height() {
+            return Err(StorageError::Pruned(height + 1).into());
+        }
+        if let Some(missing) = (height + 1..=tip).find(|h| !self.storage.undo.contains_key(h)) {
+            return Err(StorageError::MissingUndo(missing).into());
+        }
+
+        let mut returned = Vec::new();
+        for block_height in (height + 1..=tip).rev() {
+            let undo = self.storage.undo.remove(&block_height);
+            let undo = undo.ok_or(StorageError::MissingUndo(block_height))?;
+            let block = self.chain.pop().expect("the chain reaches the tip");
+            undo.restore(&mut self.storage);
+            returned.splice(0..0, block.transactions);
+        }
+        let count = returned.len();
+        let ids: HashSet<String> = returned.iter().map(Transaction::hash).collect();
+        returned.extend(
+            self.pending_transactions
+                .drain(..)
+                .filter(|tx| !ids.contains(&tx.hash())),
+        );
+        self.pending_transactions = returned;
+        self.storage.height = Some(height);
+        self.metrics.set_chain_height(height);
+        self.metrics
+            .set_mempool_size(self.pending_transactions.len());
+
+        self.storage.save()?;
+        self.storage.rewrite_blocks(self.unpruned_blocks())?;
+        // Snapshots of the blocks taken off no longer describe this chain
+        for snapshot in self.storage.snapshots() {
+            if snapshot > height {
+                self.storage.remove_snapshot(snapshot)?;
+            }
+        }
+        info!(height, transactions = count, "Chain rewound");
+        Ok(count)
+    }
+
+    /// Records an applied transaction in the history of every account it touched.
+    fn index_transaction(&mut self, height: u64, tx_index: usize, transaction: &Transaction) {
+        let native = NATIVE_ASSET.to_string();
+        let entries = match transaction {
+            Transaction::CreateAccount { id, balance, .. } => {
+                vec![(id, Direction::Created, &native, None, *balance)]
+            }
+            Transaction::Transfer {
+                from, to, amount, ..
+            } => vec![
+                (from, Direction::Sent, &native, Some(to), *amount),
+                (to, Direction::Received, &native, Some(from), *amount),
+            ],
+            Transaction::IssueAsset {
+                issuer,
+                symbol,
+                supply,
+                ..
+            } => vec![(issuer, Direction::Issued, symbol, None, *supply)],
+            Transaction::TransferAsset {
+                from,
+                to,
+                asset,
+                amount,
+                ..
+            } => vec![
+                (from, Direction::Sent, asset, Some(to), *amount),
+                (to, Direction::Received, asset, Some(from), *amount),
+            ],
+            Transaction::Mint {
+                authority,
+                to,
+                asset,
+                amount,
+                ..
+            } => vec![(to, Direction::Minted, asset, Some(authority), *amount)],
+            Transaction::Burn {
+                authority,
+                asset,
+                amount,
+                ..
+            } => vec![(authority, Direction::Burned, asset, None, *amount)],
+        };
+        for (account, direction, asset, counterparty, amount) in entries {
+            let balance = self.storage.balance_of(account, asset);
+            self.storage
+                .history
+                .entry(account.clone())
+                .or_default()
+                .push(HistoryEntry {
+                    height,
+                    tx_index,
+                    direction,
+                    asset: asset.clone(),
+                    counterparty: counterparty.cloned(),
+                    amount,
+                    balance,
+                });
+        }
+    }
+
+    fn apply_transaction(&mut self, transaction: &Transaction) -> ReceiptStatus {
+        if let Err(e) = self.check_chain_id(transaction.chain_id()) {
+            return ReceiptStatus::Dropped {
+                reason: e.to_string(),
+            };
+        }
+        match transaction {
+            Transaction::CreateAccount { id, balance, .. } => {
+                debug!(account = %id, "Processing create-account transaction");
+                // Insert only if the account does not already exist to prevent double processing
+                if self.storage.accounts.contains_key(id) {
+                    return ReceiptStatus::Dropped {
+                        reason: ValidationError::AccountExists.to_string(),
+                    };
+                }
+                if *balance > 0 && !self.genesis.allows_free_balances() {
+                    return ReceiptStatus::Dropped {
+                        reason: ValidationError::InitialBalanceNotAllowed.to_string(),
+                    };
+                }
+                let Some(minted) = self.storage.minted.checked_add(*balance) else {
+                    debug!(account = %id, balance, "Account balance would overflow the supply");
+                    return ReceiptStatus::Failed {
+                        reason: ValidationError::Overflow.to_string(),
+                    };
+                };
+                self.storage.minted = minted;
+                self.storage.accounts.insert(id.clone(), *balance);
+                ReceiptStatus::Included
+            }
+            Transaction::Transfer {
+                from,
+                to,
+                amount,
+                nonce,
+                ..
+            } => self.apply_transfer(from, to, NATIVE_ASSET, *amount, *nonce),
+            Transaction::TransferAsset {
+                from,
+                to,
+                asset,
+                amount,
+                nonce,
+                ..
+            } => self.apply_transfer(from, to, asset, *amount, *nonce),
+            Transaction::IssueAsset {
+                issuer,
+                symbol,
+                decimals,
+                supply,
+                ..
+            } => {
+                debug!(%issuer, %symbol, supply, "Processing issue-asset transaction");
+                if let Some(reason) = self.check_new_asset(issuer, symbol, *decimals).err() {
+                    return ReceiptStatus::Dropped {
+                        reason: reason.to_string(),
+                    };
+                }
+                self.storage.assets.insert(
+                    symbol.clone(),
+                    Asset {
+                        symbol: symbol.clone(),
+                        decimals: *decimals,
+                        issuer: Some(issuer.clone()),
+                        minted: *supply,
+                        burned: 0,
+                    },
+                );
+                self.storage.set_balance(issuer, symbol, *supply);
+                ReceiptStatus::Included
+            }
+            Transaction::Mint {
+                authority,
+                to,
+                asset,
+                amount,
+                nonce,
+                ..
+            } => {
+                if let Some(dropped) = self.check_nonce(authority, *nonce) {
+                    return dropped;
+                }
+                if let Err(error) = self.check_authority(authority, asset) {
+                    debug!(%authority, %asset, %error, "Dropping mint");
+                    return ReceiptStatus::Dropped {
+                        reason: error.to_string(),
+                    };
+                }
+                self.storage.nonces.insert(authority.clone(), nonce + 1);
+                let minted = self.supply_counters(asset).0.checked_add(*amount);
+                let balance = self.storage.balance_of(to, asset).checked_add(*amount);
+                let (Some(minted), Some(balance)) = (minted, balance) else {
+                    debug!(%authority, %to, %asset, amount, "Mint would overflow");
+                    return ReceiptStatus::Failed {
+                        reason: ValidationError::Overflow.to_string(),
+                    };
+                };
+                self.set_supply_counters(asset, minted, self.supply_counters(asset).1);
+                self.storage.accounts.entry(to.clone()).or_insert(0);
+                self.storage.set_balance(to, asset, balance);
+                ReceiptStatus::Included
+            }
+            Transaction::Burn {
+                authority,
+                asset,
+                amount,
+                nonce,
+                ..
+            } => {
+                if let Some(dropped) = self.check_nonce(authority, *nonce) {
+                    return dropped;
+                }
+                if let Err(error) = self.check_authority(authority, asset) {
+                    debug!(%authority, %asset, %error, "Dropping burn");
+                    return ReceiptStatus::Dropped {
+                        reason: error.to_string(),
+                    };
+                }
+                self.storage.nonces.insert(authority.clone(), nonce + 1);
+                let Some(balance) = self
+                    .storage
+                    .balance_of(authority, asset)
+                    .checked_sub(*amount)
+                else {
+                    debug!(%authority, %asset, amount, "Insufficient funds for burn");
+                    return ReceiptStatus::Failed {
+                        reason: ValidationError::InsufficientFunds.to_string(),
+                    };
+                };
+                // Cannot overflow: burns never exceed what was minted
+                let (minted, burned) = self.supply_counters(asset);
+                self.set_supply_counters(asset, minted, burned + amount);
+                self.storage.set_balance(authority, asset, balance);
+                ReceiptStatus::Included
+            }
+        }
+    }
+
+    /// Returns a dropped receipt if `nonce` is not the next one expected from `account`.
+    fn check_nonce(&self, account: &str, nonce: u64) -> Option<ReceiptStatus> {
+        let expected = self.storage.nonces.get(account).copied().unwrap_or(0);
+        if nonce == expected {
+            return None;
+        }
+        let error = ConsensusError::InvalidNonce {
+            expected,
+            actual: nonce,
+        };
+        debug!(%account, %error, "Dropping transaction");
+        Some(ReceiptStatus::Dropped {
+            reason: error.to_string(),
+        })
+    }
+
+    /// Checks that `account` may mint and burn `asset`: the genesis authority
+    /// for the native coin, the issuer for any other asset.
+    fn check_authority(&self, account: &str, asset: &str) -> Result<()> {
+        let info = self
+            .storage
+            .asset(asset)
+            .ok_or_else(|| NotFoundError::Asset(asset.to_string()))?;
+        let authority = if asset == NATIVE_ASSET {
+            self.genesis.authority.as_deref()
+        } else {
+            info.issuer.as_deref()
+        };
+        if authority != Some(account) {
+            return Err(ValidationError::Unauthorized {
+                account: account.to_string(),
+                asset: asset.to_string(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Minted and burned totals of an existing asset.
+    fn supply_counters(&self, asset: &str) -> (u64, u64) {
+        self.storage
+            .asset(asset)
+            .map_or((0, 0), |info| (info.minted, info.burned))
+    }
+
+    fn set_supply_counters(&mut self, asset: &str, minted: u64, burned: u64) {
+        if asset == NATIVE_ASSET {
+            self.storage.minted = minted;
+            self.storage.burned = burned;
+        } else if let Some(info) = self.storage.assets.get_mut(asset) {
+            info.minted = minted;
+            info.burned = burned;
+        }
+    }
+
+    fn apply_transfer(
+        &mut self,
+        from: &str,
+        to: &str,
+        asset: &str,
+        amount: u64,
+        nonce: u64,
+    ) -> ReceiptStatus {
+        if let Some(dropped) = self.check_nonce(from, nonce) {
+            return dropped;
+        }
+        if !self.storage.accounts.contains_key(from) {
+            let error = NotFoundError::Account(from.to_string());
+            debug!(%from, %error, "Dropping transfer");
+            return ReceiptStatus::Dropped {
+                reason: error.to_string(),
+            };
+        }
+        if self.storage.asset(asset).is_none() {
+            let error = NotFoundError::Asset(asset.to_string());
+            debug!(%from, %error, "Dropping transfer");
+            return ReceiptStatus::Dropped {
+                reason: error.to_string(),
+            };
+        }
+        self.storage.nonces.insert(from.to_string(), nonce + 1);
+        let sender_balance = self.storage.balance_of(from, asset);
+        let Some(new_sender_balance) = sender_balance.checked_sub(amount) else {
+            debug!(%from, %to, %asset, amount, "Insufficient funds for transfer");
+            return ReceiptStatus::Failed {
+                reason: ValidationError::InsufficientFunds.to_string(),
+            };
+        };
+        if from == to {
+            return ReceiptStatus::Included;
+        }
+        let receiver_balance = self.storage.balance_of(to, asset);
+        let Some(new_receiver_balance) = receiver_balance.checked_add(amount) else {
+            debug!(%from, %to, %asset, amount, "Transfer would overflow the receiver balance");
+            return ReceiptStatus::Failed {
+                reason: ValidationError::Overflow.to_string(),
+            };
+        };
+        // Receiving funds opens an account, whatever the asset
+        self.storage.accounts.entry(to.to_string()).or_insert(0);
+        self.storage.set_balance(from, asset, new_sender_balance);
+        self.storage.set_balance(to, asset, new_receiver_balance);
+        ReceiptStatus::Included
+    }
+
+    /// Checks that `symbol` can be issued by `issuer`, against the current ledger state.
+    fn check_new_asset(&self, issuer: &str, symbol: &str, decimals: u8) -> Result<()> {
+        asset::validate_symbol(symbol)?;
+        if decimals > MAX_DECIMALS {
+            return Err(ValidationError::InvalidDecimals { max: MAX_DECIMALS }.into());
+        }
+        if self.storage.asset(symbol).is_some() {
+            return Err(ValidationError::AssetExists(symbol.to_string()).into());
+        }
+        if !self.storage.accounts.contains_key(issuer) {
+            return Err(NotFoundError::Account(issuer.to_string()).into());
+        }
+        Ok(())
+    }
+
+    /// Verifies ledger invariants: the balances must add up to the total supply.
+    pub fn check_invariants(&self) -> Result<()> {
+        self.storage.check_invariants()?;
+        Ok(())
+    }
+
+    /// Current time on the chain's clock, in seconds since the Unix epoch.
+    pub fn now(&self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards");
+        (now + self.clock_offset).as_secs()
     }
 
     fn get_last_block_hash(&self) -> String {
         if let Some(last_block) = self.chain.last() {
-            // In a real blockchain, this would be a cryptographic hash
-            last_block.timestamp.to_string() + &last_block.prev_block_hash
+            last_block.hash()
         } else {
             "".to_string()
         }
     }
 
-    pub fn create_account(&mut self, id: &str, balance: u64) -> Result<(), String> {
+    /// Height of the chain tip; the genesis block is at height 0.
+    pub fn height(&self) -> u64 {
+        self.chain.len().saturating_sub(1) as u64
+    }
+
+    /// Looks up a block either by its height or by its hash.
+    pub fn get_block(&self, id: &str) -> Option<(u64, &Block)> {
+        if let Ok(height) = id.parse::<u64>() {
+            return self.chain.get(height as usize).map(|block| (height, block));
+        }
+        self.chain
+            .iter()
+            .enumerate()
+            .find(|(_, block)| block.hash() == id)
+            .map(|(height, block)| (height as u64, block))
+    }
+
+    /// Looks up a transaction by hash, first in the chain and then in the mempool.
+    /// Returns the height of the including block, or `None` for a pending transaction.
+    /// Finds a transaction and the height of its block, or None for the
+    /// height while it is pending. The newest copy wins, as in `get_receipt`.
+    pub fn get_transaction(&self, hash: &str) -> Option<(Option<u64>, &Transaction)> {
+        if let Some(tx) = self
+            .pending_transactions
+            .iter()
+            .find(|tx| tx.hash() == hash)
+        {
+            return Some((None, tx));
+        }
+        self.chain
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(height, block)| {
+                let tx = block.transactions.iter().find(|tx| tx.hash() == hash)?;
+                Some((Some(height as u64), tx))
+            })
+    }
+
+    /// Finds the newest receipt recorded for a transaction, along with the
+    /// height of its block. A dropped transaction can be submitted again, so
+    /// older receipts may be stale.
+    pub fn get_receipt(&self, id: &str) -> Option<(u64, &Receipt)> {
+        self.chain
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(height, block)| {
+                block
+                    .receipts
+                    .iter()
+                    .find(|receipt| receipt.tx_id == id)
+                    .map(|receipt| (height as u64, receipt))
+            })
+    }
+
+    /// Status of a transaction. A pending copy is newer than any receipt.
+    pub fn tx_status(&self, id: &str) -> Option<TxStatus> {
+        if self.pending_transactions.iter().any(|tx| tx.hash() == id) {
+            return Some(TxStatus::Pending);
+        }
+        self.get_receipt(id)
+            .map(|(height, receipt)| TxStatus::from_receipt(height, receipt))
+    }
+
+    pub fn create_account(&mut self, id: &str, balance: u64) -> Result<String> {
+        let transaction = self.check_create_account(id, balance);
+        self.admit(transaction)
+    }
+
+    fn check_create_account(&self, id: &str, balance: u64) -> Result<Transaction> {
         if !self.is_active {
-            return Err("Blockchain node is not running".to_string());
+            return Err(ValidationError::NodeInactive.into());
         }
 
         if self.storage.accounts.contains_key(id) {
-            return Err("Account already exists".to_string());
+            return Err(ValidationError::AccountExists.into());
+        }
+
+        if balance > 0 && !self.genesis.allows_free_balances() {
+            return Err(ValidationError::InitialBalanceNotAllowed.into());
+        }
+
+        if self.storage.minted.checked_add(balance).is_none() {
+            return Err(ValidationError::Overflow.into());
         }
 
         let transaction = Transaction::CreateAccount {
+            chain_id: self.genesis.chain_id.clone(),
             id: id.to_string(),
             balance,
         };
-        self.pending_transactions.push(transaction);
+        if self
+            .pending_transactions
+            .iter()
+            .any(|tx| tx.hash() == transaction.hash())
+        {
+            return Err(ValidationError::DuplicateTransaction.into());
+        }
 
-        Ok(())
+        Ok(transaction)
     }
 
-    pub fn list_accounts(&self) -> String {
-        if self.storage.accounts.is_empty() {
-            return "No accounts found.".to_string();
-        }
-        let mut accounts_list = String::new();
-        for (id, balance) in &self.storage.accounts {
-            accounts_list.push_str(&format!("Account ID: {}, Balance: {}\n", id, balance));
-        }
-        accounts_list
+    /// Native balance of every account, keyed by account id.
+    pub fn list_accounts(&self) -> BTreeMap<String, u64> {
+        self.storage
+            .accounts
+            .iter()
+            .map(|(id, &balance)| (id.clone(), balance))
+            .collect()
+    }
+
+    pub fn transfer(&mut self, from: &str, to: &str, amount: u64) -> Result<String> {
+        let transaction = self.check_transfer(from, to, amount);
+        self.admit(transaction)
     }
 
-    pub fn transfer(&mut self, from: &str, to: &str, amount: u64) -> Result<(), String> {
+    fn check_transfer(&self, from: &str, to: &str, amount: u64) -> Result<Transaction> {
         let from_balance = self
             .storage
             .accounts
-            .get_mut(from)
-            .ok_or_else(|| "From account not found".to_string())?;
+            .get(from)
+            .ok_or_else(|| NotFoundError::Account(from.to_string()))?;
         if *from_balance < amount {
-            return Err("Insufficient funds".to_string());
+            return Err(ValidationError::InsufficientFunds.into());
         }
 
-        // Create a transfer transaction and add it to the pending transactions list
-        let transaction = Transaction::Transfer {
+        // Create a transfer transaction to add to the pending transactions list
+        Ok(Transaction::Transfer {
+            chain_id: self.genesis.chain_id.clone(),
             from: from.to_string(),
             to: to.to_string(),
             amount,
-        };
-        self.pending_transactions.push(transaction);
-
-        Ok(())
+            nonce: self.next_nonce(from),
+        })
     }
 
-    pub fn balance(&self, account: &str) -> Result<u64, String> {
+    pub fn balance(&self, account: &str) -> Result<u64> {
         self.storage
             .accounts
             .get(account)
             .copied()
-            .ok_or_else(|| "Account not found".to_string())
+            .ok_or_else(|| NotFoundError::Account(account.to_string()).into())
+    }
+
+    pub fn issue_asset(
+        &mut self,
+        issuer: &str,
+        symbol: &str,
+        decimals: u8,
+        supply: u64,
+    ) -> Result<String> {
+        let transaction = self.check_issue_asset(issuer, symbol, decimals, supply);
+        self.admit(transaction)
+    }
+
+    fn check_issue_asset(
+        &self,
+        issuer: &str,
+        symbol: &str,
+        decimals: u8,
+        supply: u64,
+    ) -> Result<Transaction> {
+        if !self.is_active {
+            return Err(ValidationError::NodeInactive.into());
+        }
+        self.check_new_asset(issuer, symbol, decimals)?;
+        let already_pending = self.pending_transactions.iter().any(
+            |tx| matches!(tx, Transaction::IssueAsset { symbol: pending, .. } if pending == symbol),
+        );
+        if already_pending {
+            return Err(ValidationError::DuplicateTransaction.into());
+        }
+        Ok(Transaction::IssueAsset {
+            chain_id: self.genesis.chain_id.clone(),
+            issuer: issuer.to_string(),
+            symbol: symbol.to_string(),
+            decimals,
+            supply,
+        })
+    }
+
+    pub fn transfer_asset(
+        &mut self,
+        from: &str,
+        to: &str,
+        asset: &str,
+        amount: u64,
+    ) -> Result<String> {
+        let transaction = self.check_transfer_asset(from, to, asset, amount);
+        self.admit(transaction)
+    }
+
+    fn check_transfer_asset(
+        &self,
+        from: &str,
+        to: &str,
+        asset: &str,
+        amount: u64,
+    ) -> Result<Transaction> {
+        if !self.storage.accounts.contains_key(from) {
+            return Err(NotFoundError::Account(from.to_string()).into());
+        }
+        if self.storage.asset(asset).is_none() {
+            return Err(NotFoundError::Asset(asset.to_string()).into());
+        }
+        if self.storage.balance_of(from, asset) < amount {
+            return Err(ValidationError::InsufficientFunds.into());
+        }
+        Ok(Transaction::TransferAsset {
+            chain_id: self.genesis.chain_id.clone(),
+            from: from.to_string(),
+            to: to.to_string(),
+            asset: asset.to_string(),
+            amount,
+            nonce: self.next_nonce(from),
+        })
+    }
+
+    pub fn mint(&mut self, authority: &str, to: &str, asset: &str, amount: u64) -> Result<String> {
+        let transaction = self.check_mint(authority, to, asset, amount);
+        self.admit(transaction)
+    }
+
+    fn check_mint(
+        &self,
+        authority: &str,
+        to: &str,
+        asset: &str,
+        amount: u64,
+    ) -> Result<Transaction> {
+        self.check_authority(authority, asset)?;
+        if self.supply_counters(asset).0.checked_add(amount).is_none() {
+            return Err(ValidationError::Overflow.into());
+        }
+        Ok(Transaction::Mint {
+            chain_id: self.genesis.chain_id.clone(),
+            authority: authority.to_string(),
+            to: to.to_string(),
+            asset: asset.to_string(),
+            amount,
+            nonce: self.next_nonce(authority),
+        })
+    }
+
+    pub fn burn(&mut self, authority: &str, asset: &str, amount: u64) -> Result<String> {
+        let transaction = self.check_burn(authority, asset, amount);
+        self.admit(transaction)
+    }
+
+    fn check_burn(&self, authority: &str, asset: &str, amount: u64) -> Result<Transaction> {
+        self.check_authority(authority, asset)?;
+        if self.storage.balance_of(authority, asset) < amount {
+            return Err(ValidationError::InsufficientFunds.into());
+        }
+        Ok(Transaction::Burn {
+            chain_id: self.genesis.chain_id.clone(),
+            authority: authority.to_string(),
+            asset: asset.to_string(),
+            amount,
+            nonce: self.next_nonce(authority),
+        })
+    }
+
+    /// Balance of an existing account in any asset.
+    pub fn asset_balance(&self, account: &str, asset: &str) -> Result<u64> {
+        if !self.storage.accounts.contains_key(account) {
+            return Err(NotFoundError::Account(account.to_string()).into());
+        }
+        if self.storage.asset(asset).is_none() {
+            return Err(NotFoundError::Asset(asset.to_string()).into());
+        }
+        Ok(self.storage.balance_of(account, asset))
+    }
+
+    /// Balance of an account in `asset` as of the block at `height`, read
+    /// back from the history index.
+    pub fn balance_at(&self, account: &str, asset: &str, height: u64) -> Result<u64> {
+        let entries = self.history_at(account, height)?;
+        if self.storage.asset(asset).is_none() {
+            return Err(NotFoundError::Asset(asset.to_string()).into());
+        }
+        Ok(entries
+            .iter()
+            .rev()
+            .find(|entry| entry.asset == asset)
+            .map_or(0, |entry| entry.balance))
+    }
+
+    /// All balances of an account as of the block at `height`, keyed by
+    /// asset symbol.
+    pub fn balances_at(&self, account: &str, height: u64) -> Result<BTreeMap<String, u64>> {
+        let mut balances = BTreeMap::from([(NATIVE_ASSET.to_string(), 0)]);
+        for entry in self.history_at(account, height)? {
+            balances.insert(entry.asset.clone(), entry.balance);
+        }
+        balances.retain(|asset, &mut amount| amount > 0 || asset == NATIVE_ASSET);
+        Ok(balances)
+    }
+
+    /// History entries of an account up to and including the block at
+    /// `height`, oldest first. Fails if the account did not exist yet.
+    fn history_at(&self, account: &str, height: u64) -> Result<&[HistoryEntry]> {
+        if height > self.height() {
+            return Err(NotFoundError::Block(height.to_string()).into());
+        }
+        let entries = self
+            .storage
+            .history
+            .get(account)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let entries = &entries[..entries.partition_point(|entry| entry.height <= height)];
+        // The authority is created by the genesis without a history entry
+        if entries.is_empty() && self.genesis.authority.as_deref() != Some(account) {
+            return Err(NotFoundError::Account(account.to_string()).into());
+        }
+        Ok(entries)
+    }
+
+    /// Height of the last block mined at or before `timestamp`.
+    pub fn height_at(&self, timestamp: u64) -> Result<u64> {
+        let blocks = self
+            .chain
+            .partition_point(|block| block.timestamp <= timestamp);
+        blocks
+            .checked_sub(1)
+            .map(|height| height as u64)
+            .ok_or_else(|| NotFoundError::Block(format!("at {}", timestamp)).into())
+    }
+
+    /// All balances of an account, keyed by asset symbol.
+    pub fn balances(&self, account: &str) -> Result<BTreeMap<String, u64>> {
+        if !self.storage.accounts.contains_key(account) {
+            return Err(NotFoundError::Account(account.to_string()).into());
+        }
+        Ok(self.storage.balances(account))
+    }
+
+    /// Metadata and current supply of an asset, the native coin included.
+    pub fn asset_info(&self, symbol: &str) -> Result<Asset> {
+        self.storage
+            .asset(symbol)
+            .ok_or_else(|| NotFoundError::Asset(symbol.to_string()).into())
+    }
+
+    /// Returns one page of the account's history, newest first. Pages start at 1.
+    pub fn history(&self, account: &str, page: usize, page_size: usize) -> Result<HistoryPage> {
+        if page == 0 || page_size == 0 {
+            return Err(ValidationError::InvalidPage.into());
+        }
+        if !self.storage.accounts.contains_key(account) {
+            return Err(NotFoundError::Account(account.to_string()).into());
+        }
+        let entries = self
+            .storage
+            .history
+            .get(account)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        Ok(HistoryPage::new(account, entries, page, page_size))
     }
 
     pub fn start_with_mocks<R: BufRead, W: Write>(&mut self, reader: R, mut writer: W) {
@@ -279,32 +1970,337 @@ impl Blockchain {
 
             match self.process_command(&line) {
                 Ok(message) => writeln!(writer, "{}", message).unwrap(),
-                Err(e) => writeln!(writer, "Error: {}", e).unwrap(),
+                Err(e) => writeln!(writer, "{}", e.to_wire()).unwrap(),
             }
         }
     }
 
-    pub fn process_command(&mut self, command: &str) -> Result<String, String> {
-        let parts: Vec<&str> = command.split_whitespace().collect();
+    /// Answers a binary message from a trusted, in-process caller.
+    pub fn process_message(&mut self, message: Message) -> Message {
+        self.process_message_as(message, &Scope::Admin.into())
+    }
+
+    /// Answers a binary message from a session granted `grant`.
+    pub fn process_message_as(&mut self, message: Message, grant: &Grant) -> Message {
+        let required = match message {
+            Message::SubmitTransaction(_) => Scope::Submit,
+            _ => Scope::Read,
+        };
+        let response = match message {
+            _ if grant.scope < required => Err(ValidationError::PermissionDenied {
+                command: message.name().to_string(),
+                required,
+            }
+            .into()),
+            Message::GetChainInfo => Ok(Message::ChainInfo {
+                chain_id: self.genesis.chain_id.clone(),
+                genesis_hash: self.genesis_hash(),
+                height: self.height(),
+            }),
+            Message::SubmitTransaction(transaction) => {
+                Self::check_acts_for(grant, transaction.authority())
+                    .and_then(|()| self.submit_transaction(transaction))
+                    .map(Message::Submitted)
+            }
+            Message::GetBlock(height) => match self.chain.get(height as usize) {
+                Some(block) if block.is_pruned() => Err(StorageError::Pruned(height).into()),
+                Some(block) => Ok(Message::Block(block.clone())),
+                None => Err(NotFoundError::Block(height.to_string()).into()),
+            },
+            _ => Err(Error::from(NetworkError::UnexpectedMessage)),
+        };
+        response.unwrap_or_else(|error| {
+            warn!(code = error.code(), %error, "Message failed");
+            Message::Error {
+                code: error.code().to_string(),
+                message: error.to_string(),
+            }
+        })
+    }
+
+    /// Runs a command from a trusted, in-process caller.
+    pub fn process_command(&mut self, command: &str) -> Result<String> {
+        self.process_command_as(command, &Scope::Admin.into())
+    }
+
+    /// Runs a command from a session granted `grant`, refusing commands that
+    /// need a wider scope and mints or burns by another account.
+    pub fn process_command_as(&mut self, command: &str, grant: &Grant) -> Result<String> {
+        let mut words = command.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let required = Scope::required_for(name);
+        if grant.scope < required {
+            return Err(ValidationError::PermissionDenied {
+                command: name.to_string(),
+                required,
+            }
+            .into());
+        }
+        if matches!(name, "mint" | "burn") {
+            Self::check_acts_for(grant, words.next())?;
+        }
+        if let Some(json) = command.trim().strip_prefix("submit-tx ") {
+            let transaction: Transaction =
+                serde_json::from_str(json).map_err(|_| ValidationError::InvalidCommand)?;
+            Self::check_acts_for(grant, transaction.authority())?;
+            return self.submit_transaction(transaction);
+        }
+        let mut parts: Vec<&str> = command.split_whitespace().collect();
+        // Clients name the chain they mean to talk to, so a command aimed at
+        // one network is never applied on another.
+        if let [.., "--chain-id", chain_id] = parts.as_slice() {
+            self.check_chain_id(chain_id)?;
+            parts.truncate(parts.len() - 2);
+        }
+        // Balance queries may name a past block, by height or by timestamp
+        let mut at = None;
+        if let ["balance" | "balances", .., flag @ ("--at" | "--at-time"), value] = parts.as_slice()
+        {
+            at = Some(match *flag {
+                "--at" => ValidationError::parse_number("height", value)?,
+                _ => self.height_at(ValidationError::parse_number("timestamp", value)?)?,
+            });
+            parts.truncate(parts.len() - 2);
+        }
         match parts.as_slice() {
             ["create-account", id, balance] => {
-                let balance = balance
-                    .parse::<u64>()
-                    .map_err(|_| "Invalid balance".to_string())?;
-                self.create_account(id, balance)
-                    .map(|_| format!("Account '{}' created with balance {}", id, balance))
+                let balance: u64 = ValidationError::parse_number("balance", balance)?;
+                self.create_account(id, balance).map(|tx_id| {
+                    submitted(
+                        format!("Account creation for '{}' with balance {}", id, balance),
+                        tx_id,
+                    )
+                })
             }
             ["transfer", from, to, amount] => {
-                let amount = amount
-                    .parse::<u64>()
-                    .map_err(|_| "Invalid amount".to_string())?;
-                self.transfer(from, to, amount)
-                    .map(|_| format!("Transferred {} from '{}' to '{}'", amount, from, to))
+                let amount: u64 = ValidationError::parse_number("amount", amount)?;
+                self.transfer(from, to, amount).map(|tx_id| {
+                    submitted(
+                        format!("Transfer of {} from '{}' to '{}'", amount, from, to),
+                        tx_id,
+                    )
+                })
+            }
+            ["balance", account] => match at {
+                Some(height) => self
+                    .balance_at(account, NATIVE_ASSET, height)
+                    .map(|balance| {
+                        format!("Balance of '{}' at height {}: {}", account, height, balance)
+                    }),
+                None => self
+                    .balance(account)
+                    .map(|balance| format!("Balance of '{}': {}", account, balance)),
+            },
+            ["balance", account, asset] => match at {
+                Some(height) => self.balance_at(account, asset, height).map(|balance| {
+                    format!(
+                        "Balance of '{}' in {} at height {}: {}",
+                        account, asset, height, balance
+                    )
+                }),
+                None => self
+                    .asset_balance(account, asset)
+                    .map(|balance| format!("Balance of '{}' in {}: {}", account, asset, balance)),
+            },
+            ["balances", account] => match at {
+                Some(height) => {
+                    let balances = self.balances_at(account, height)?;
+                    Ok(to_pretty(
+                        &json!({ "account": account, "height": height, "balances": balances }),
+                    ))
+                }
+                None => {
+                    let balances = self.balances(account)?;
+                    Ok(to_pretty(
+                        &json!({ "account": account, "balances": balances }),
+                    ))
+                }
+            },
+            ["issue-asset", issuer, symbol, decimals, supply] => {
+                let decimals: u8 = ValidationError::parse_number("decimals", decimals)?;
+                let supply: u64 = ValidationError::parse_number("supply", supply)?;
+                self.issue_asset(issuer, symbol, decimals, supply)
+                    .map(|tx_id| {
+                        submitted(
+                            format!("Issue of {} {} by '{}'", supply, symbol, issuer),
+                            tx_id,
+                        )
+                    })
+            }
+            ["transfer-asset", from, to, asset, amount] => {
+                let amount: u64 = ValidationError::parse_number("amount", amount)?;
+                self.transfer_asset(from, to, asset, amount).map(|tx_id| {
+                    submitted(
+                        format!(
+                            "Transfer of {} {} from '{}' to '{}'",
+                            amount, asset, from, to
+                        ),
+                        tx_id,
+                    )
+                })
+            }
+            ["mint", authority, to, asset, amount] => {
+                let amount: u64 = ValidationError::parse_number("amount", amount)?;
+                self.mint(authority, to, asset, amount).map(|tx_id| {
+                    submitted(format!("Mint of {} {} to '{}'", amount, asset, to), tx_id)
+                })
             }
-            ["balance", account] => self
-                .balance(account)
-                .map(|balance| format!("Balance of '{}': {}", account, balance)),
-            _ => Err("Invalid command".to_string()),
+            ["burn", authority, asset, amount] => {
+                let amount: u64 = ValidationError::parse_number("amount", amount)?;
+                self.burn(authority, asset, amount).map(|tx_id| {
+                    submitted(
+                        format!("Burn of {} {} by '{}'", amount, asset, authority),
+                        tx_id,
+                    )
+                })
+            }
+            ["asset-info", symbol] => {
+                let asset = self.asset_info(symbol)?;
+                Ok(to_pretty(&json!({
+                    "symbol": asset.symbol,
+                    "decimals": asset.decimals,
+                    "issuer": asset.issuer,
+                    "minted": asset.minted,
+                    "burned": asset.burned,
+                    "supply": self.storage.supply(&asset)?,
+                })))
+            }
+            ["history", account, rest @ ..] if rest.len() <= 2 => {
+                let page = match rest.first() {
+                    Some(page) => ValidationError::parse_number("page", page)?,
+                    None => 1,
+                };
+                let page_size = match rest.get(1) {
+                    Some(size) => ValidationError::parse_number("page size", size)?,
+                    None => history::DEFAULT_PAGE_SIZE,
+                };
+                let page = self.history(account, page, page_size)?;
+                Ok(serde_json::to_string_pretty(&page).expect("Failed to serialize history"))
+            }
+            ["list-accounts"] => Ok(to_pretty(&json!({ "accounts": self.list_accounts() }))),
+            ["height"] => Ok(json!({ "height": self.height() }).to_string()),
+            ["get-block", id] => {
+                let (height, block) = self
+                    .get_block(id)
+                    .ok_or_else(|| NotFoundError::Block(id.to_string()))?;
+                let mut block = block.to_json(height);
+                block["final"] = self.is_final(height).into();
+                Ok(to_pretty(&block))
+            }
+            ["get-tx", hash] => {
+                let (height, tx) = self
+                    .get_transaction(hash)
+                    .ok_or_else(|| NotFoundError::Transaction(hash.to_string()))?;
+                Ok(to_pretty(&json!({
+                    "hash": hash,
+                    "status": self.tx_status(hash).map(|status| status.label()),
+                    "block_height": height,
+                    "final": height.is_some_and(|height| self.is_final(height)),
+                    "transaction": tx,
+                    "receipt": height
+                        .and_then(|_| self.get_receipt(hash))
+                        .map(|(_, receipt)| &receipt.status),
+                })))
+            }
+            ["tx-status", id] => self
+                .tx_status(id)
+                .map(|status| {
+                    let is_final = status.height().is_some_and(|height| self.is_final(height));
+                    let finality = if is_final { " (final)" } else { "" };
+                    format!("Transaction {}: {}{}", id, status, finality)
+                })
+                .ok_or_else(|| NotFoundError::Transaction(id.to_string()).into()),
+            ["mempool"] => Ok(to_pretty(&json!({
+                "size": self.pending_transactions.len(),
+                "transactions": self
+                    .pending_transactions
+                    .iter()
+                    .map(|tx| json!({ "hash": tx.hash(), "transaction": tx }))
+                    .collect::<Vec<_>>(),
+            }))),
+            ["rewind", height] => {
+                let height = ValidationError::parse_number("height", height)?;
+                let count = self.rewind_to(height)?;
+                Ok(format!(
+                    "Rewound to height {}, {} transactions returned to the mempool",
+                    height, count
+                ))
+            }
+            ["mine"] => {
+                if !self.is_active {
+                    return Err(ValidationError::NodeInactive.into());
+                }
+                let height = self.height();
+                self.mine_block();
+                match self.chain.get(height as usize + 1) {
+                    Some(block) => Ok(format!(
+                        "Mined block {} with {} transactions",
+                        height + 1,
+                        block.transactions().len()
+                    )),
+                    None => Ok("No transactions to mine".to_string()),
+                }
+            }
+            ["advance-time", seconds] => {
+                let seconds: u64 = ValidationError::parse_number("seconds", seconds)?;
+                self.clock_offset += Duration::from_secs(seconds);
+                Ok(format!("Clock advanced by {} seconds", seconds))
+            }
+            ["check-invariants"] => {
+                self.check_invariants()?;
+                Ok(format!(
+                    "Invariants hold: total supply {} across {} accounts",
+                    self.storage.total_supply()?,
+                    self.storage.accounts.len()
+                ))
+            }
+            ["chain-info"] => Ok(to_pretty(&json!({
+                "height": self.height(),
+                "best_block_hash": self.get_last_block_hash(),
+                "finalized_height": self.finalized_height(),
+                "mode": self.retention.mode.to_string(),
+                "pruned_height": self.pruned_height(),
+                "latest_snapshot": self.storage.snapshots().last(),
+                "chain_id": self.genesis.chain_id,
+                "genesis_hash": self.genesis_hash(),
+                "block_time_secs": self.block_time.as_secs(),
+                "max_block_bytes": self.genesis.consensus.max_block_bytes,
+                "max_block_transactions": self.genesis.consensus.max_block_transactions,
+                "pending_transactions": self.pending_transactions.len(),
+                "accounts": self.storage.accounts.len(),
+                "total_supply": self.storage.total_supply()?,
+                "is_active": self.is_active,
+            }))),
+            _ => Err(ValidationError::InvalidCommand.into()),
         }
     }
 }
+
+/// Whether a read failed because the socket's read timeout expired.
+/// `error` encoded as a message for a binary request, or as text otherwise.
+fn error_response(request: &[u8], error: &Error) -> Vec<u8> {
+    let (_, request) = auth::split_header(request);
+    if Message::is_binary(request) {
+        return encoding::encode(&Message::Error {
+            code: error.code().to_string(),
+            message: error.to_string(),
+        });
+    }
+    error.to_wire().into_bytes()
+}
+
+fn is_timeout(error: &std::io::Error) -> bool {
+    matches!(
+        error.kind(),
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+    )
+}
+
+fn to_pretty(value: &serde_json::Value) -> String {
+    serde_json::to_string_pretty(value).expect("Failed to serialize JSON value")
+}
+
+/// Response to a command that submitted transaction `tx_id`.
+fn submitted(summary: String, tx_id: String) -> String {
+    to_pretty(&json!({ "submitted": summary, "tx_id": tx_id }))
+}
@@ -0,0 +1,121 @@
+//! Limits protecting the node's port from clients that send too much, too
+//! often or too slowly.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+/// Rate limiter entries kept before idle, refilled ones are forgotten.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// What the node accepts from its clients.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectionLimits {
+    /// Connections served at once. Further ones are refused.
+    pub max_connections: u64,
+    /// How long a request may take to arrive.
+    pub read_timeout: Duration,
+    /// Largest request, in bytes, including the auth header.
+    pub max_request_bytes: usize,
+    /// Requests a client may send per second on average.
+    pub requests_per_second: f64,
+    /// Requests a client may send at once after being idle.
+    pub burst: f64,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        ConnectionLimits {
+            max_connections: 64,
+            read_timeout: Duration::from_secs(10),
+            max_request_bytes: 1 << 20,
+            requests_per_second: 20.0,
+            burst: 40.0,
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket per client, where a client is an auth token or else an IP.
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: HashMap<String, Bucket>,
+}
+
+impl RateLimiter {
+    /// Takes one request from `client`'s bucket, or returns false if it is
+    /// empty.
+    pub fn allow(&mut self, client: &str, limits: &ConnectionLimits, now: Instant) -> bool {
+        let refill = |bucket: &Bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            (bucket.tokens + elapsed * limits.requests_per_second).min(limits.burst)
+        };
+        if self.buckets.len() >= MAX_TRACKED_CLIENTS && !self.buckets.contains_key(client) {
+            // Refilled buckets are no different from new ones. If none is,
+            // the client seen least recently is forgotten.
+            self.buckets
+                .retain(|_, bucket| refill(bucket) < limits.burst);
+            if self.buckets.len() >= MAX_TRACKED_CLIENTS {
+                let oldest = self
+                    .buckets
+                    .iter()
+                    .min_by_key(|(_, bucket)| bucket.updated)
+                    .map(|(client, _)| client.clone());
+                if let Some(oldest) = oldest {
+                    self.buckets.remove(&oldest);
+                }
+            }
+        }
+        let bucket = self.buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: limits.burst,
+            updated: now,
+        });
+        bucket.tokens = refill(bucket);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+/// A TCP stream whose reads all share one deadline. Before each read the
+/// socket timeout is cut to the time left, so a client cannot stretch a
+/// request past the limit by sending it a byte at a time.
+pub struct DeadlineStream {
+    stream: TcpStream,
+    deadline: Instant,
+}
+
+impl DeadlineStream {
+    pub fn new(stream: TcpStream, deadline: Instant) -> Self {
+        DeadlineStream { stream, deadline }
+    }
+}
+
+impl Read for DeadlineStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.read(buf)
+    }
+}
+
+impl Write for DeadlineStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
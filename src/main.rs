@@ -1,76 +1,779 @@
+use blockchain_simulation::asset;
+use blockchain_simulation::auth::AuthConfig;
+use blockchain_simulation::error::{Error, NetworkError};
+use blockchain_simulation::genesis::{self, GenesisConfig};
+use blockchain_simulation::receipt::TxStatus;
+use blockchain_simulation::retention::NodeMode;
+use blockchain_simulation::storage::{self, Storage};
+use blockchain_simulation::transport::{self, StaticKey};
 use blockchain_simulation::Blockchain;
+use blockchain_simulation::{client, repl, script};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde_json::{json, Value};
 use std::env;
-use std::io::Write;
-use std::net::TcpStream;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use tracing::debug;
+use tracing_subscriber::EnvFilter;
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+/// The node rejected the command, a script assertion failed, or a local
+/// operation such as a migration failed.
+const EXIT_FAILURE: u8 = 1;
+/// The node could not be reached.
+const EXIT_UNREACHABLE: u8 = 3;
+/// `--wait` gave up before the transaction settled.
+const EXIT_TIMEOUT: u8 = 4;
+// Usage errors exit with 2, as reported by clap.
 
-    if args.len() < 2 {
-        print_usage_and_exit();
+/// Blockchain simulation node and client.
+#[derive(Parser)]
+#[command(name = "b", bin_name = "b", version)]
+struct Cli {
+    /// How to print command responses.
+    #[arg(long, global = true, value_enum, default_value_t = Output::Table)]
+    output: Output,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Output {
+    /// One JSON object per response: `{"ok": true, "result": ...}` or
+    /// `{"ok": false, "error": {"code": ..., "message": ...}}`.
+    Json,
+    /// Human-readable output.
+    Table,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Start a node that serves commands and mines blocks
+    StartNode,
+    /// Upgrade a ledger file to the current storage format
+    DbMigrate {
+        /// Report what would change without writing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Ledger file to upgrade
+        #[arg(default_value = storage::DEFAULT_PATH)]
+        path: PathBuf,
+    },
+    /// Open an interactive shell
+    Repl(TargetArgs),
+    /// Run a command script with assertions
+    RunScript {
+        /// Script to run
+        file: PathBuf,
+        #[command(flatten)]
+        target: TargetArgs,
+    },
+    /// Create an account
+    CreateAccount {
+        /// Account id
+        #[arg(value_parser = account_id)]
+        id: String,
+        /// Initial native balance; must be 0 on networks with a mint authority
+        balance: u64,
+        #[command(flatten)]
+        wait: WaitArgs,
+    },
+    /// Transfer native coin between accounts
+    Transfer {
+        /// Sending account
+        #[arg(value_parser = account_id)]
+        from: String,
+        /// Receiving account
+        #[arg(value_parser = account_id)]
+        to: String,
+        /// Amount in the smallest unit of the asset
+        amount: u64,
+        #[command(flatten)]
+        wait: WaitArgs,
+    },
+    /// List accounts and their native balances
+    ListAccounts,
+    /// Show the balance of an account, in native coin or the given asset
+    Balance {
+        /// Account id
+        #[arg(value_parser = account_id)]
+        account: String,
+        /// Asset symbol (default: the native coin)
+        #[arg(value_parser = asset_symbol)]
+        asset: Option<String>,
+        #[command(flatten)]
+        at: AtArgs,
+    },
+    /// Show all balances of an account
+    Balances {
+        /// Account id
+        #[arg(value_parser = account_id)]
+        account: String,
+        #[command(flatten)]
+        at: AtArgs,
+    },
+    /// Issue a new asset, crediting its supply to the issuer
+    IssueAsset {
+        /// Issuing account, credited with the supply
+        #[arg(value_parser = account_id)]
+        issuer: String,
+        /// 1 to 12 uppercase letters or digits
+        #[arg(value_parser = asset_symbol)]
+        symbol: String,
+        /// Number of decimal places
+        #[arg(value_parser = clap::value_parser!(u8).range(..=asset::MAX_DECIMALS as i64))]
+        decimals: u8,
+        /// Initial supply
+        supply: u64,
+        #[command(flatten)]
+        wait: WaitArgs,
+    },
+    /// Transfer any asset between accounts
+    TransferAsset {
+        /// Sending account
+        #[arg(value_parser = account_id)]
+        from: String,
+        /// Receiving account
+        #[arg(value_parser = account_id)]
+        to: String,
+        /// Asset symbol
+        #[arg(value_parser = asset_symbol)]
+        asset: String,
+        /// Amount in the smallest unit of the asset
+        amount: u64,
+        #[command(flatten)]
+        wait: WaitArgs,
+    },
+    /// Mint new units of an asset
+    Mint {
+        /// Mint authority of the asset
+        #[arg(value_parser = account_id)]
+        authority: String,
+        /// Receiving account
+        #[arg(value_parser = account_id)]
+        to: String,
+        /// Asset symbol
+        #[arg(value_parser = asset_symbol)]
+        asset: String,
+        /// Amount in the smallest unit of the asset
+        amount: u64,
+        #[command(flatten)]
+        wait: WaitArgs,
+    },
+    /// Burn units of an asset held by its authority
+    Burn {
+        /// Mint authority of the asset
+        #[arg(value_parser = account_id)]
+        authority: String,
+        /// Asset symbol
+        #[arg(value_parser = asset_symbol)]
+        asset: String,
+        /// Amount in the smallest unit of the asset
+        amount: u64,
+        #[command(flatten)]
+        wait: WaitArgs,
+    },
+    /// Show an asset's metadata and supply
+    AssetInfo {
+        /// 1 to 12 uppercase letters or digits
+        #[arg(value_parser = asset_symbol)]
+        symbol: String,
+    },
+    /// Show the transaction history of an account, newest first
+    History {
+        /// Account id
+        #[arg(value_parser = account_id)]
+        account: String,
+        /// Page number, starting at 1
+        #[arg(value_parser = clap::value_parser!(u64).range(1..))]
+        page: Option<u64>,
+        /// Entries per page (default 20)
+        #[arg(value_parser = clap::value_parser!(u64).range(1..))]
+        page_size: Option<u64>,
+    },
+    /// Show the chain height
+    Height,
+    /// Show a block by height or hash
+    GetBlock {
+        /// Block height or hash
+        id: String,
+    },
+    /// Show a transaction by hash
+    GetTx {
+        /// Transaction hash
+        hash: String,
+    },
+    /// Show whether a transaction is pending, confirmed or failed
+    TxStatus {
+        /// Transaction id, as returned on submission
+        id: String,
+    },
+    /// List the transactions waiting to be mined
+    Mempool,
+    /// Show the chain id, genesis hash and consensus parameters
+    ChainInfo,
+    /// Check that balances add up to the tracked supply
+    CheckInvariants,
+    /// Undo the blocks above a height, returning their transactions to the
+    /// mempool (admin)
+    Rewind {
+        /// Height of the block that becomes the tip
+        height: u64,
+    },
+}
+
+#[derive(Args)]
+struct WaitArgs {
+    /// Block until the transaction is mined, then print its receipt
+    #[arg(long)]
+    wait: bool,
+    /// Number of blocks, the transaction's own included, to wait for
+    #[arg(long, requires = "wait", default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    confirmations: u64,
+    /// Give up waiting after this many seconds
+    #[arg(long, requires = "wait", value_name = "SECONDS", default_value_t = 60)]
+    timeout: u64,
+}
+
+#[derive(Args)]
+struct AtArgs {
+    /// Show the balance as of the block at this height
+    #[arg(long, value_name = "HEIGHT", conflicts_with = "at_time")]
+    at: Option<u64>,
+    /// Show the balance as of the last block mined at or before this Unix timestamp
+    #[arg(long, value_name = "TIMESTAMP")]
+    at_time: Option<u64>,
+}
+
+impl AtArgs {
+    /// Suffix selecting a past block on a balance query.
+    fn suffix(&self) -> String {
+        match (self.at, self.at_time) {
+            (Some(height), _) => format!(" --at {}", height),
+            (_, Some(timestamp)) => format!(" --at-time {}", timestamp),
+            _ => String::new(),
+        }
     }
+}
 
-    match args[1].as_str() {
-        "b" => {
-            match args.get(2).map(String::as_str) {
-                Some("start-node") => {
-                    let blockchain = Arc::new(Mutex::new(Blockchain::new(Duration::from_secs(10))));
-                    println!("Starting blockchain node...");
-                    Blockchain::start_node(blockchain);
-                    // Keep the main thread alive to allow mining in the background
-                    loop {
-                        std::thread::sleep(Duration::from_secs(60));
-                    }
-                }
-                Some("create-account") if args.len() == 5 => {
-                    let command = format!("create-account {} {}", args[3], args[4]);
-                    send_command_to_node(command);
-                }
-                Some("transfer") if args.len() == 6 => {
-                    let command = format!("transfer {} {} {}", args[3], args[4], args[5]);
-                    send_command_to_node(command);
-                }
-                Some("list-accounts") => {
-                    let command = "list-accounts".to_string();
-                    send_command_to_node(command);
+#[derive(Args)]
+struct TargetArgs {
+    /// Drive the node at ADDRESS (default `B_ADDR`) instead of an in-process chain
+    #[arg(long, value_name = "ADDRESS", num_args = 0..=1, default_missing_value = "")]
+    remote: Option<String>,
+}
+
+impl Command {
+    /// Wait options of the commands that submit a transaction.
+    fn wait_args(&self) -> Option<&WaitArgs> {
+        match self {
+            Command::CreateAccount { wait, .. }
+            | Command::Transfer { wait, .. }
+            | Command::IssueAsset { wait, .. }
+            | Command::TransferAsset { wait, .. }
+            | Command::Mint { wait, .. }
+            | Command::Burn { wait, .. } => Some(wait),
+            _ => None,
+        }
+    }
+
+    /// Command line sent to the node, and whether it submits a transaction.
+    /// `None` for commands the CLI runs itself.
+    fn node_command(&self) -> Option<(String, bool)> {
+        let submit = |command: String| Some((command, true));
+        let query = |command: &str| Some((command.to_string(), false));
+        match self {
+            Command::StartNode
+            | Command::DbMigrate { .. }
+            | Command::Repl(_)
+            | Command::RunScript { .. } => None,
+            Command::CreateAccount { id, balance, .. } => {
+                submit(format!("create-account {} {}", id, balance))
+            }
+            Command::Transfer {
+                from, to, amount, ..
+            } => submit(format!("transfer {} {} {}", from, to, amount)),
+            Command::IssueAsset {
+                issuer,
+                symbol,
+                decimals,
+                supply,
+                ..
+            } => submit(format!(
+                "issue-asset {} {} {} {}",
+                issuer, symbol, decimals, supply
+            )),
+            Command::TransferAsset {
+                from,
+                to,
+                asset,
+                amount,
+                ..
+            } => submit(format!(
+                "transfer-asset {} {} {} {}",
+                from, to, asset, amount
+            )),
+            Command::Mint {
+                authority,
+                to,
+                asset,
+                amount,
+                ..
+            } => submit(format!("mint {} {} {} {}", authority, to, asset, amount)),
+            Command::Burn {
+                authority,
+                asset,
+                amount,
+                ..
+            } => submit(format!("burn {} {} {}", authority, asset, amount)),
+            Command::ListAccounts => query("list-accounts"),
+            Command::Balance { account, asset, at } => match asset {
+                Some(asset) => query(&format!("balance {} {}{}", account, asset, at.suffix())),
+                None => query(&format!("balance {}{}", account, at.suffix())),
+            },
+            Command::Balances { account, at } => {
+                query(&format!("balances {}{}", account, at.suffix()))
+            }
+            Command::AssetInfo { symbol } => query(&format!("asset-info {}", symbol)),
+            Command::History {
+                account,
+                page,
+                page_size,
+            } => {
+                let mut command = format!("history {}", account);
+                if page.is_some() || page_size.is_some() {
+                    command.push_str(&format!(" {}", page.unwrap_or(1)));
                 }
-                Some("balance") if args.len() == 4 => {
-                    let command = format!("balance {}", args[3]);
-                    send_command_to_node(command);
+                if let Some(page_size) = page_size {
+                    command.push_str(&format!(" {}", page_size));
                 }
-                _ => print_usage_and_exit(),
+                query(&command)
+            }
+            Command::Height => query("height"),
+            Command::GetBlock { id } => query(&format!("get-block {}", id)),
+            Command::GetTx { hash } => query(&format!("get-tx {}", hash)),
+            Command::TxStatus { id } => query(&format!("tx-status {}", id)),
+            Command::Mempool => query("mempool"),
+            Command::ChainInfo => query("chain-info"),
+            Command::CheckInvariants => query("check-invariants"),
+            Command::Rewind { height } => query(&format!("rewind {}", height)),
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    init_logging();
+    // `b` used to be a required first argument; it is still accepted
+    let args = env::args()
+        .enumerate()
+        .filter(|(index, arg)| !(*index == 1 && arg == "b"))
+        .map(|(_, arg)| arg);
+    let cli = Cli::parse_from(args);
+
+    if let Some((command, submits)) = cli.command.node_command() {
+        let command = if submits {
+            // Tag submissions with the chain id so another network rejects them
+            format!("{} --chain-id {}", command, chain_id())
+        } else {
+            command
+        };
+        let wait = cli.command.wait_args().filter(|args| args.wait);
+        return send_command_to_node(&command, wait, cli.output);
+    }
+
+    match cli.command {
+        Command::StartNode => start_node(),
+        Command::DbMigrate { dry_run, path } => migrate_storage(&path, dry_run),
+        Command::Repl(target) => {
+            let history = env::var("HOME")
+                .ok()
+                .map(|home| Path::new(&home).join(".b_history"));
+            if let Err(e) = repl::run(script_target(target), history.as_deref()) {
+                eprintln!("REPL failed: {}", e);
+                return ExitCode::from(EXIT_FAILURE);
             }
+            ExitCode::SUCCESS
         }
-        _ => print_usage_and_exit(),
+        Command::RunScript { file, target } => run_script(&file, script_target(target)),
+        _ => unreachable!("node commands are sent above"),
+    }
+}
+
+/// Sends node logs to stderr. Verbosity follows `RUST_LOG` (default `info`) and
+/// `B_LOG_FORMAT=json` switches to one JSON object per line.
+fn init_logging() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    if env::var("B_LOG_FORMAT").is_ok_and(|format| format == "json") {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
+
+/// Account ids are 1 to 64 letters, digits, `-`, `_` or `.`.
+fn account_id(id: &str) -> Result<String, String> {
+    let valid = (1..=64).contains(&id.len())
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err("expected 1 to 64 letters, digits, '-', '_' or '.'".to_string());
     }
+    Ok(id.to_string())
 }
 
-fn print_usage_and_exit() {
-    println!("Usage:");
-    println!("b start-node");
-    println!("b create-account <id> <balance>");
-    println!("b transfer <from-account> <to-account> <amount>");
-    println!("b list-accounts");
-    println!("b balance <account>");
-    std::process::exit(1);
+fn asset_symbol(symbol: &str) -> Result<String, String> {
+    asset::validate_symbol(symbol).map_err(|e| e.to_string())?;
+    Ok(symbol.to_string())
 }
 
-fn send_command_to_node(command: String) {
-    let command = command.trim_end(); // Ensure no trailing newline or spaces
-    println!("Sending command: '{}'", command);
-    match TcpStream::connect("127.0.0.1:3000") {
-        Ok(mut stream) => {
-            if let Err(e) = stream.write_all(command.as_bytes()) {
-                println!("Failed to send command: {}", e);
-                return;
+fn start_node() -> ExitCode {
+    if Path::new(storage::DEFAULT_PATH).exists() {
+        let status = migrate_storage(Path::new(storage::DEFAULT_PATH), false);
+        if status != ExitCode::SUCCESS {
+            return status;
+        }
+    }
+    let storage = match Storage::open(storage::DEFAULT_PATH) {
+        Ok(storage) => storage,
+        Err(e) => {
+            eprintln!("Failed to open '{}': {}", storage::DEFAULT_PATH, e);
+            return ExitCode::from(EXIT_FAILURE);
+        }
+    };
+    let mut blockchain = Blockchain::with_genesis(load_genesis(), storage);
+    blockchain.listen_addr = node_addr();
+    blockchain.peers = env::var("B_PEERS")
+        .map(|peers| {
+            peers
+                .split(',')
+                .filter(|peer| !peer.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    blockchain.metrics_port = env_number("B_METRICS_PORT");
+    let key_path = env::var("B_NODE_KEY")
+        .ok()
+        .or_else(|| secure_profile().then(|| "node.key".to_string()));
+    if let Some(path) = key_path {
+        match StaticKey::load_or_generate(&path) {
+            Ok(key) => {
+                println!("Node key: {}", key.public_hex());
+                blockchain.node_key = Some(key);
+            }
+            Err(e) => {
+                eprintln!("Failed to load node key '{}': {}", path, e);
+                return ExitCode::from(EXIT_FAILURE);
+            }
+        }
+    }
+    blockchain.require_encryption = secure_profile();
+    let limits = &mut blockchain.limits;
+    if let Some(max) = env_number("B_MAX_CONNECTIONS") {
+        limits.max_connections = max;
+    }
+    if let Some(secs) = env_number("B_READ_TIMEOUT_SECS") {
+        limits.read_timeout = Duration::from_secs(secs);
+    }
+    if let Some(max) = env_number("B_MAX_REQUEST_BYTES") {
+        limits.max_request_bytes = max;
+    }
+    if let Some(rate) = env_number("B_RATE_LIMIT") {
+        limits.requests_per_second = rate;
+    }
+    if let Some(burst) = env_number("B_RATE_BURST") {
+        limits.burst = burst;
+    }
+    if let Ok(path) = env::var("B_CHECKPOINTS") {
+        match genesis::load_checkpoints(&path) {
+            Ok(checkpoints) => blockchain.checkpoints = checkpoints,
+            Err(e) => {
+                eprintln!("Failed to load checkpoints '{}': {}", path, e);
+                return ExitCode::from(EXIT_FAILURE);
+            }
+        }
+    }
+    if let Ok(mode) = env::var("B_MODE") {
+        match mode.parse() {
+            Ok(mode) => blockchain.retention.mode = mode,
+            Err(e) => {
+                eprintln!("Invalid B_MODE: {}, expected 'archive' or 'pruned'", e);
+                return ExitCode::from(EXIT_FAILURE);
+            }
+        }
+    }
+    if let Some(blocks) = env_number("B_KEEP_BLOCKS") {
+        blockchain.retention.keep_blocks = blocks;
+    }
+    blockchain.retention.snapshot_interval = env_number("B_SNAPSHOT_INTERVAL");
+    if blockchain.retention.mode == NodeMode::Pruned
+        && blockchain.retention.snapshot_interval.is_none()
+    {
+        eprintln!(
+            "B_MODE=pruned needs B_SNAPSHOT_INTERVAL, pruned blocks are rebuilt from snapshots"
+        );
+        return ExitCode::from(EXIT_FAILURE);
+    }
+    if let Some(depth) = env_number("B_UNDO_DEPTH") {
+        blockchain.retention.undo_depth = depth;
+    }
+    if let Ok(path) = env::var("B_AUTH") {
+        match AuthConfig::load(&path) {
+            Ok(auth) => blockchain.auth = Some(auth),
+            Err(e) => {
+                eprintln!("Failed to load auth config '{}': {}", path, e);
+                return ExitCode::from(EXIT_FAILURE);
             }
-            stream.flush().expect("Failed to flush the stream");
         }
+    }
+    let blockchain = Arc::new(Mutex::new(blockchain));
+    println!("Starting blockchain node...");
+    if let Err(e) = Blockchain::start_node(blockchain) {
+        match std::error::Error::source(&e) {
+            Some(source) => eprintln!("{}: {}", e, source),
+            None => eprintln!("{}", e),
+        }
+        return ExitCode::from(EXIT_FAILURE);
+    }
+    // Keep the main thread alive to allow mining in the background
+    loop {
+        std::thread::sleep(Duration::from_secs(60));
+    }
+}
+
+/// Value of the environment variable `name`, ignored unless it parses.
+fn env_number<T: std::str::FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok().and_then(|value| value.parse().ok())
+}
+
+fn run_script(file: &Path, mut target: repl::Target) -> ExitCode {
+    let script = match File::open(file) {
+        Ok(script) => script,
+        Err(e) => {
+            eprintln!("Failed to open script '{}': {}", file.display(), e);
+            return ExitCode::from(EXIT_FAILURE);
+        }
+    };
+    match script::run(&mut target, BufReader::new(script), std::io::stdout()) {
+        Ok(summary) => {
+            println!(
+                "Script passed: {} commands, {} assertions",
+                summary.commands, summary.assertions
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Script failed at {}: {}", file.display(), e);
+            ExitCode::from(EXIT_FAILURE)
+        }
+    }
+}
+
+/// Chain for `repl` and `run-script`: a node reached with `--remote [address]`,
+/// or else a fresh in-process chain from the genesis config.
+fn script_target(args: TargetArgs) -> repl::Target {
+    match args.remote {
+        Some(addr) if addr.is_empty() => repl::Target::Remote {
+            addr: node_addr(),
+            session: session(),
+        },
+        Some(addr) => repl::Target::Remote {
+            addr,
+            session: session(),
+        },
+        None => {
+            let mut blockchain = Blockchain::with_genesis(load_genesis(), Storage::default());
+            blockchain.is_active = true;
+            repl::Target::Embedded(Box::new(blockchain))
+        }
+    }
+}
+
+/// Upgrades the ledger file at `path` to the current format, or with
+/// `dry_run` only reports what would change.
+fn migrate_storage(path: &Path, dry_run: bool) -> ExitCode {
+    let path_name = path.display();
+    match Storage::migrate_file(path, dry_run) {
+        Ok(report) if report.is_up_to_date() => println!("{}: {}", path_name, report),
+        Ok(report) if dry_run => println!("{}: would migrate\n{}", path_name, report),
+        Ok(report) => println!("{}: migrated\n{}", path_name, report),
+        Err(e) => {
+            eprintln!("Failed to migrate '{}': {}", path_name, e);
+            return ExitCode::from(EXIT_FAILURE);
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// Genesis configuration from the file named by `B_GENESIS`, or the dev defaults.
+fn load_genesis() -> GenesisConfig {
+    match env::var("B_GENESIS") {
+        Ok(path) => GenesisConfig::load(&path).unwrap_or_else(|e| {
+            eprintln!("Failed to load genesis config '{}': {}", path, e);
+            std::process::exit(EXIT_FAILURE.into());
+        }),
+        Err(_) => GenesisConfig::default(),
+    }
+}
+
+/// Chain the CLI submits transactions to: `B_CHAIN_ID`, else the chain id of
+/// the genesis file.
+fn chain_id() -> String {
+    env::var("B_CHAIN_ID").unwrap_or_else(|_| load_genesis().chain_id)
+}
+
+/// Address of the node, from `B_ADDR` (default `127.0.0.1:3000`).
+fn node_addr() -> String {
+    env::var("B_ADDR").unwrap_or_else(|_| client::DEFAULT_ADDR.to_string())
+}
+
+/// Whether `B_PROFILE` selects the secure profile, where every session is
+/// encrypted. The default `dev` profile allows plaintext.
+fn secure_profile() -> bool {
+    env::var("B_PROFILE").is_ok_and(|profile| profile == "secure")
+}
+
+/// How the CLI talks to the node: with the auth token in `B_TOKEN`, and
+/// encrypted when `B_NODE_PUBKEY` pins the node key. The secure profile
+/// requires that key.
+fn session() -> client::Session {
+    let node_key = env::var("B_NODE_PUBKEY").ok().map(|hex| {
+        transport::from_hex(&hex).unwrap_or_else(|| {
+            eprintln!("B_NODE_PUBKEY is not a hex encoded key");
+            std::process::exit(EXIT_FAILURE.into());
+        })
+    });
+    if secure_profile() && node_key.is_none() {
+        eprintln!("The secure profile needs B_NODE_PUBKEY, the node's public key");
+        std::process::exit(EXIT_FAILURE.into());
+    }
+    let encryption = (secure_profile() || node_key.is_some()).then(|| client::Encryption {
+        key: StaticKey::generate(),
+        node_key,
+    });
+    client::Session {
+        token: env::var("B_TOKEN").ok(),
+        encryption,
+    }
+}
+
+/// Sends `command` and prints the response. With `wait`, the transaction it
+/// submitted is then followed until it settles.
+fn send_command_to_node(command: &str, wait: Option<&WaitArgs>, output: Output) -> ExitCode {
+    debug!(%command, "Sending command");
+    let response = match client::send_command_as(&node_addr(), &session(), command) {
+        Ok(response) => response,
+        Err(e) => {
+            let message = match std::error::Error::source(&e) {
+                Some(source) => format!("{}: {}", e, source),
+                None => e.to_string(),
+            };
+            print_error(output, Error::from(e).code(), &message);
+            return ExitCode::from(EXIT_UNREACHABLE);
+        }
+    };
+    if let Some((code, message)) = Error::parse_wire(&response) {
+        print_error(output, code, message);
+        return ExitCode::from(EXIT_FAILURE);
+    }
+    if let Some(wait) = wait {
+        let Some(tx_id) = client::submitted_tx_id(&response) else {
+            let error = Error::from(NetworkError::UnexpectedMessage);
+            print_error(
+                output,
+                error.code(),
+                "The node did not report a transaction id",
+            );
+            return ExitCode::from(EXIT_FAILURE);
+        };
+        return wait_for_transaction(&tx_id, wait, output);
+    }
+    match output {
+        Output::Json => {
+            let result = serde_json::from_str(&response).unwrap_or(Value::String(response));
+            println!("{}", json!({ "ok": true, "result": result }));
+        }
+        Output::Table => match serde_json::from_str(&response) {
+            Ok(Value::Object(fields)) => print_table(&fields),
+            _ => println!("{}", response),
+        },
+    }
+    ExitCode::SUCCESS
+}
+
+fn wait_for_transaction(tx_id: &str, wait: &WaitArgs, output: Output) -> ExitCode {
+    let timeout = Duration::from_secs(wait.timeout);
+    let settled = match client::wait_for_transaction(
+        &node_addr(),
+        &session(),
+        tx_id,
+        wait.confirmations,
+        timeout,
+    ) {
+        Ok(settled) => settled,
         Err(e) => {
-            println!("Failed to connect: {}", e);
-            return;
+            let error = Error::from(e);
+            print_error(output, error.code(), &error.to_string());
+            return ExitCode::from(match error.code() {
+                "timeout" => EXIT_TIMEOUT,
+                "network_io" => EXIT_UNREACHABLE,
+                _ => EXIT_FAILURE,
+            });
+        }
+    };
+    let confirmed = matches!(settled.status, TxStatus::Confirmed { .. });
+    match output {
+        Output::Json => println!(
+            "{}",
+            json!({
+                "ok": confirmed,
+                "result": {
+                    "tx_id": tx_id,
+                    "status": settled.status.label(),
+                    "block_height": settled.height,
+                    "confirmations": settled.confirmations,
+                    "receipt": settled.receipt,
+                },
+            })
+        ),
+        Output::Table => {
+            println!("Transaction {}: {}", tx_id, settled.status);
+            println!("Confirmations: {}", settled.confirmations);
         }
     }
+    if confirmed {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(EXIT_FAILURE)
+    }
+}
+
+fn print_error(output: Output, code: &str, message: &str) {
+    match output {
+        Output::Json => println!(
+            "{}",
+            json!({ "ok": false, "error": { "code": code, "message": message } })
+        ),
+        Output::Table => eprintln!("Error [{}]: {}", code, message),
+    }
+}
+
+/// Prints a JSON object as aligned `key  value` rows. Nested values are shown
+/// as compact JSON.
+fn print_table(fields: &serde_json::Map<String, Value>) {
+    let width = fields.keys().map(String::len).max().unwrap_or(0);
+    for (key, value) in fields {
+        let value = match value {
+            Value::String(text) => text.clone(),
+            other => other.to_string(),
+        };
+        println!("{:width$}  {}", key, value, width = width);
+    }
 }
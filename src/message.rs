@@ -0,0 +1,118 @@
+//! Binary messages exchanged between nodes.
+//!
+//! A connection carries either a text command from the CLI or one encoded
+//! `Message`. The two are told apart by the first byte: encoded data starts
+//! with the encoding version, which is never the first byte of a command.
+
+use crate::encoding::{self, Decode, Decoder, Encode};
+use crate::error::DecodeError;
+use crate::{Block, Transaction};
+
+#[derive(Clone, Debug)]
+pub enum Message {
+    /// Asks a peer which network it runs and how far its chain is.
+    GetChainInfo,
+    ChainInfo {
+        chain_id: String,
+        genesis_hash: String,
+        height: u64,
+    },
+    /// Relays a transaction to a peer's mempool.
+    SubmitTransaction(Transaction),
+    /// Id of a transaction the peer admitted.
+    Submitted(String),
+    GetBlock(u64),
+    Block(Block),
+    /// A request failed; `code` is the stable error code.
+    Error {
+        code: String,
+        message: String,
+    },
+}
+
+impl Message {
+    /// Whether `bytes` hold an encoded message rather than a text command.
+    pub fn is_binary(bytes: &[u8]) -> bool {
+        bytes.first() == Some(&encoding::VERSION)
+    }
+
+    /// Name of the message kind, as reported in errors.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Message::GetChainInfo => "get-chain-info",
+            Message::ChainInfo { .. } => "chain-info",
+            Message::SubmitTransaction(_) => "submit-transaction",
+            Message::Submitted(_) => "submitted",
+            Message::GetBlock(_) => "get-block",
+            Message::Block(_) => "block",
+            Message::Error { .. } => "error",
+        }
+    }
+}
+
+impl Encode for Message {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            Message::GetChainInfo => out.push(0),
+            Message::ChainInfo {
+                chain_id,
+                genesis_hash,
+                height,
+            } => {
+                out.push(1);
+                chain_id.encode_to(out);
+                genesis_hash.encode_to(out);
+                height.encode_to(out);
+            }
+            Message::SubmitTransaction(transaction) => {
+                out.push(2);
+                transaction.encode_to(out);
+            }
+            Message::Submitted(tx_id) => {
+                out.push(3);
+                tx_id.encode_to(out);
+            }
+            Message::GetBlock(height) => {
+                out.push(4);
+                height.encode_to(out);
+            }
+            Message::Block(block) => {
+                out.push(5);
+                block.encode_to(out);
+            }
+            Message::Error { code, message } => {
+                out.push(6);
+                code.encode_to(out);
+                message.encode_to(out);
+            }
+        }
+    }
+}
+
+impl Decode for Message {
+    fn decode_from(input: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        let message = match input.byte()? {
+            0 => Message::GetChainInfo,
+            1 => Message::ChainInfo {
+                chain_id: String::decode_from(input)?,
+                genesis_hash: String::decode_from(input)?,
+                height: u64::decode_from(input)?,
+            },
+            2 => Message::SubmitTransaction(Transaction::decode_from(input)?),
+            3 => Message::Submitted(String::decode_from(input)?),
+            4 => Message::GetBlock(u64::decode_from(input)?),
+            5 => Message::Block(Block::decode_from(input)?),
+            6 => Message::Error {
+                code: String::decode_from(input)?,
+                message: String::decode_from(input)?,
+            },
+            tag => {
+                return Err(DecodeError::InvalidTag {
+                    kind: "message",
+                    tag,
+                })
+            }
+        };
+        Ok(message)
+    }
+}
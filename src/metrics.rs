@@ -0,0 +1,256 @@
+use crate::error::{Error, NetworkError, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// How long a scrape may take to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Upper bounds, in seconds, of the duration histogram buckets.
+const DURATION_BUCKETS: &[f64] = &[0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+struct Histogram {
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            counts: vec![0; DURATION_BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bound, count) in DURATION_BUCKETS.iter().zip(&mut self.counts) {
+            if seconds <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (bound, count) in DURATION_BUCKETS.iter().zip(&self.counts) {
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count);
+        let _ = writeln!(out, "{}_sum {}", name, self.sum);
+        let _ = writeln!(out, "{}_count {}", name, self.count);
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    blocks_mined: u64,
+    transactions_admitted: u64,
+    transactions_rejected: BTreeMap<&'static str, u64>,
+    mempool_size: usize,
+    chain_height: u64,
+    connections_total: u64,
+    connections_active: u64,
+    requests_refused: BTreeMap<&'static str, u64>,
+    mining_duration: Histogram,
+    storage_write_duration: Histogram,
+}
+
+/// Node counters and gauges, rendered in the Prometheus text exposition format.
+#[derive(Default)]
+pub struct Metrics {
+    inner: Mutex<Inner>,
+}
+
+impl Metrics {
+    pub fn record_block_mined(&self, duration: Duration, height: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.blocks_mined += 1;
+        inner.chain_height = height;
+        inner.mining_duration.observe(duration);
+    }
+
+    pub fn record_admitted(&self) {
+        self.inner.lock().unwrap().transactions_admitted += 1;
+    }
+
+    /// Counts a rejected transaction under the error's stable code.
+    pub fn record_rejected(&self, error: &Error) {
+        let mut inner = self.inner.lock().unwrap();
+        *inner.transactions_rejected.entry(error.code()).or_default() += 1;
+    }
+
+    pub fn set_chain_height(&self, height: u64) {
+        self.inner.lock().unwrap().chain_height = height;
+    }
+
+    pub fn set_mempool_size(&self, size: usize) {
+        self.inner.lock().unwrap().mempool_size = size;
+    }
+
+    pub fn connection_opened(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.connections_total += 1;
+        inner.connections_active += 1;
+    }
+
+    pub fn connection_closed(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.connections_active = inner.connections_active.saturating_sub(1);
+    }
+
+    pub fn connections_active(&self) -> u64 {
+        self.inner.lock().unwrap().connections_active
+    }
+
+    /// Counts a request or connection refused before it reached a command,
+    /// under the error's stable code.
+    pub fn record_refused(&self, error: &Error) {
+        let mut inner = self.inner.lock().unwrap();
+        *inner.requests_refused.entry(error.code()).or_default() += 1;
+    }
+
+    pub fn record_storage_write(&self, duration: Duration) {
+        self.inner
+            .lock()
+            .unwrap()
+            .storage_write_duration
+            .observe(duration);
+    }
+
+    pub fn render(&self) -> String {
+        let inner = self.inner.lock().unwrap();
+        let mut out = String::new();
+        let mut simple = |name: &str, kind: &str, help: &str, value: u64| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        };
+        simple(
+            "blockchain_blocks_mined_total",
+            "counter",
+            "Blocks mined by this node.",
+            inner.blocks_mined,
+        );
+        simple(
+            "blockchain_transactions_admitted_total",
+            "counter",
+            "Transactions accepted into the mempool.",
+            inner.transactions_admitted,
+        );
+        simple(
+            "blockchain_mempool_size",
+            "gauge",
+            "Transactions waiting in the mempool.",
+            inner.mempool_size as u64,
+        );
+        simple(
+            "blockchain_chain_height",
+            "gauge",
+            "Height of the chain tip.",
+            inner.chain_height,
+        );
+        simple(
+            "blockchain_connections_total",
+            "counter",
+            "Client connections accepted.",
+            inner.connections_total,
+        );
+        simple(
+            "blockchain_connections_active",
+            "gauge",
+            "Client connections currently being served.",
+            inner.connections_active,
+        );
+
+        let name = "blockchain_transactions_rejected_total";
+        let _ = writeln!(
+            out,
+            "# HELP {} Transactions refused admission, by reason.",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (reason, count) in &inner.transactions_rejected {
+            let _ = writeln!(out, "{}{{reason=\"{}\"}} {}", name, reason, count);
+        }
+
+        let name = "blockchain_requests_refused_total";
+        let _ = writeln!(
+            out,
+            "# HELP {} Requests and connections refused by limits or auth, by reason.",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (reason, count) in &inner.requests_refused {
+            let _ = writeln!(out, "{}{{reason=\"{}\"}} {}", name, reason, count);
+        }
+
+        inner.mining_duration.render(
+            &mut out,
+            "blockchain_mining_duration_seconds",
+            "Time spent mining a block.",
+        );
+        inner.storage_write_duration.render(
+            &mut out,
+            "blockchain_storage_write_duration_seconds",
+            "Time spent writing storage to disk.",
+        );
+        out
+    }
+}
+
+/// Serves `metrics` over HTTP on `127.0.0.1:<port>`. Every request gets the
+/// current metrics, whatever its path. Returns the bound address, so `port`
+/// may be 0.
+pub fn serve(port: u16, metrics: Arc<Metrics>) -> Result<SocketAddr> {
+    let addr = format!("127.0.0.1:{}", port);
+    let listener = TcpListener::bind(&addr).map_err(|source| NetworkError::Bind {
+        addr: addr.clone(),
+        source,
+    })?;
+    let local_addr = listener.local_addr().map_err(NetworkError::from)?;
+    info!(addr = %local_addr, "Serving metrics");
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!(error = %e, "Failed to accept metrics connection");
+                    continue;
+                }
+            };
+            // Each scrape gets its own thread, so an idle client blocks no one
+            let metrics = Arc::clone(&metrics);
+            std::thread::spawn(move || respond(stream, &metrics));
+        }
+    });
+    Ok(local_addr)
+}
+
+fn respond(mut stream: TcpStream, metrics: &Metrics) {
+    // The request itself is irrelevant, only drain what the client sent
+    let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+    let mut request = [0; 1024];
+    let _ = stream.read(&mut request);
+    let body = metrics.render();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    if let Err(e) = stream.write_all(response.as_bytes()) {
+        warn!(error = %e, "Failed to send metrics");
+    }
+}
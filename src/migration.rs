@@ -0,0 +1,186 @@
+//! On-disk format versions of the ledger file and the migrations between them.
+//!
+//! The ledger is stored as a JSON object with a `version` field. Files written
+//! before the field existed are version 0. On load, a file is upgraded one
+//! version at a time until it reaches `CURRENT_VERSION`.
+
+use crate::asset::NATIVE_ASSET;
+use crate::error::StorageError;
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// Format version written by this build.
+pub const CURRENT_VERSION: u64 = 4;
+
+/// Upgrades a ledger from `from` to `from + 1`.
+struct Migration {
+    from: u64,
+    description: &'static str,
+    /// Rewrites the ledger in place and describes each change it made.
+    apply: fn(&mut Map<String, Value>) -> Vec<String>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 0,
+        description: "track minted and burned supply",
+        apply: add_supply_tracking,
+    },
+    Migration {
+        from: 1,
+        description: "tag history entries with their asset",
+        apply: tag_history_assets,
+    },
+    Migration {
+        from: 2,
+        description: "record undo data of mined blocks",
+        apply: add_undo,
+    },
+    Migration {
+        from: 3,
+        description: "record the height of the last applied block",
+        apply: record_height,
+    },
+];
+
+/// One migration that ran, or would run, on a ledger.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationStep {
+    pub from: u64,
+    pub to: u64,
+    pub description: &'static str,
+    pub changes: Vec<String>,
+}
+
+/// What upgrading a ledger to the current version involves.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationReport {
+    pub from: u64,
+    pub to: u64,
+    pub steps: Vec<MigrationStep>,
+}
+
+impl MigrationReport {
+    pub fn is_up_to_date(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+impl fmt::Display for MigrationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_up_to_date() {
+            return write!(f, "Already at version {}", self.to);
+        }
+        write!(f, "Version {} -> {}", self.from, self.to)?;
+        for step in &self.steps {
+            write!(f, "\n  {} -> {}: {}", step.from, step.to, step.description)?;
+            if step.changes.is_empty() {
+                write!(f, " (no changes)")?;
+            }
+            for change in &step.changes {
+                write!(f, "\n    - {}", change)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Version of a ledger, 0 for files written before versioning.
+pub fn version_of(ledger: &Value) -> u64 {
+    ledger.get("version").and_then(Value::as_u64).unwrap_or(0)
+}
+
+/// Upgrades `ledger` to `CURRENT_VERSION` in place, step by step.
+pub fn migrate(ledger: &mut Value) -> Result<MigrationReport, StorageError> {
+    let from = version_of(ledger);
+    if from > CURRENT_VERSION {
+        return Err(StorageError::UnsupportedVersion {
+            found: from,
+            supported: CURRENT_VERSION,
+        });
+    }
+    let Value::Object(fields) = ledger else {
+        return Err(StorageError::Serialization(serde::de::Error::custom(
+            "ledger is not a JSON object",
+        )));
+    };
+    let steps = MIGRATIONS
+        .iter()
+        .filter(|migration| migration.from >= from)
+        .map(|migration| {
+            let changes = (migration.apply)(fields);
+            fields.insert("version".to_string(), Value::from(migration.from + 1));
+            MigrationStep {
+                from: migration.from,
+                to: migration.from + 1,
+                description: migration.description,
+                changes,
+            }
+        })
+        .collect();
+    Ok(MigrationReport {
+        from,
+        to: CURRENT_VERSION,
+        steps,
+    })
+}
+
+/// Version 0 ledgers held balances only: whatever existed counts as minted.
+fn add_supply_tracking(ledger: &mut Map<String, Value>) -> Vec<String> {
+    let mut changes = Vec::new();
+    if !ledger.contains_key("minted") {
+        let minted: u64 = ledger
+            .get("accounts")
+            .and_then(Value::as_object)
+            .map(|accounts| accounts.values().filter_map(Value::as_u64).sum())
+            .unwrap_or(0);
+        ledger.insert("minted".to_string(), Value::from(minted));
+        changes.push(format!("set minted supply to {}", minted));
+    }
+    if !ledger.contains_key("burned") {
+        ledger.insert("burned".to_string(), Value::from(0));
+        changes.push("set burned supply to 0".to_string());
+    }
+    changes
+}
+
+/// History entries written before multi-asset support were all native coin.
+fn tag_history_assets(ledger: &mut Map<String, Value>) -> Vec<String> {
+    let Some(Value::Object(history)) = ledger.get_mut("history") else {
+        return Vec::new();
+    };
+    let mut tagged = 0;
+    for entry in history
+        .values_mut()
+        .filter_map(Value::as_array_mut)
+        .flatten()
+    {
+        if let Value::Object(entry) = entry {
+            if !entry.contains_key("asset") {
+                entry.insert("asset".to_string(), Value::from(NATIVE_ASSET));
+                tagged += 1;
+            }
+        }
+    }
+    if tagged == 0 {
+        return Vec::new();
+    }
+    vec![format!(
+        "tagged {} history entries as {}",
+        tagged, NATIVE_ASSET
+    )]
+}
+
+/// Blocks mined before undo data was recorded cannot be rewound.
+fn add_undo(ledger: &mut Map<String, Value>) -> Vec<String> {
+    if ledger.contains_key("undo") {
+        return Vec::new();
+    }
+    ledger.insert("undo".to_string(), Value::Object(Map::new()));
+    vec!["started with no undo data".to_string()]
+}
+
+/// An unset height is taken to match the block log, so nothing is written.
+fn record_height(_ledger: &mut Map<String, Value>) -> Vec<String> {
+    Vec::new()
+}
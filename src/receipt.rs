@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Outcome of a transaction that was picked up by `mine_block`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum ReceiptStatus {
+    /// The transaction was executed and its effects applied.
+    Included,
+    /// The transaction was executed but had no effect; its nonce is still consumed.
+    Failed { reason: String },
+    /// The transaction was rejected without being executed.
+    Dropped { reason: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Receipt {
+    pub tx_id: String,
+    #[serde(flatten)]
+    pub status: ReceiptStatus,
+}
+
+/// Where a transaction currently stands, as reported by `tx-status`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum TxStatus {
+    Pending,
+    Confirmed { height: u64 },
+    Failed { height: u64, reason: String },
+    Dropped { height: u64, reason: String },
+}
+
+impl TxStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            TxStatus::Pending => "pending",
+            TxStatus::Confirmed { .. } => "confirmed",
+            TxStatus::Failed { .. } => "failed",
+            TxStatus::Dropped { .. } => "dropped",
+        }
+    }
+
+    /// Height of the block holding the transaction, unless it is pending.
+    pub fn height(&self) -> Option<u64> {
+        match self {
+            TxStatus::Pending => None,
+            TxStatus::Confirmed { height }
+            | TxStatus::Failed { height, .. }
+            | TxStatus::Dropped { height, .. } => Some(*height),
+        }
+    }
+
+    pub fn from_receipt(height: u64, receipt: &Receipt) -> Self {
+        match &receipt.status {
+            ReceiptStatus::Included => TxStatus::Confirmed { height },
+            ReceiptStatus::Failed { reason } => TxStatus::Failed {
+                height,
+                reason: reason.clone(),
+            },
+            ReceiptStatus::Dropped { reason } => TxStatus::Dropped {
+                height,
+                reason: reason.clone(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for TxStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxStatus::Pending => write!(f, "pending"),
+            TxStatus::Confirmed { height } => write!(f, "confirmed at height {}", height),
+            TxStatus::Failed { height, reason } => {
+                write!(f, "failed at height {}: {}", height, reason)
+            }
+            TxStatus::Dropped { height, reason } => {
+                write!(f, "dropped at height {}: {}", height, reason)
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Interactive shell over the node commands, with line editing, history and
+//! completion of command names and account ids.
+
+use crate::client::{self, Session};
+use crate::error::Error;
+use crate::Blockchain;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::path::Path;
+
+/// Commands offered for completion. `help` and `exit` are handled by the shell.
+pub const COMMANDS: &[&str] = &[
+    "create-account",
+    "transfer",
+    "balance",
+    "balances",
+    "issue-asset",
+    "transfer-asset",
+    "mint",
+    "burn",
+    "asset-info",
+    "history",
+    "list-accounts",
+    "height",
+    "get-block",
+    "get-tx",
+    "tx-status",
+    "mempool",
+    "chain-info",
+    "check-invariants",
+    "mine",
+    "advance-time",
+    "rewind",
+    "help",
+    "exit",
+];
+
+/// Chain the shell sends its commands to.
+pub enum Target {
+    /// A chain running in this process. Blocks are only produced by `mine`.
+    Embedded(Box<Blockchain>),
+    /// A node reached over the command port.
+    Remote { addr: String, session: Session },
+}
+
+impl Target {
+    /// Runs one command and returns the response as the node would send it.
+    pub fn execute(&mut self, command: &str) -> String {
+        match self {
+            Target::Embedded(blockchain) => blockchain
+                .process_command(command)
+                .unwrap_or_else(|e| e.to_wire()),
+            Target::Remote { addr, session } => client::send_command_as(addr, session, command)
+                .unwrap_or_else(|e| Error::from(e).to_wire()),
+        }
+    }
+
+    /// Known account ids, sorted.
+    pub fn accounts(&mut self) -> Vec<String> {
+        let mut accounts: Vec<String> = match self {
+            Target::Embedded(blockchain) => blockchain.storage.accounts.keys().cloned().collect(),
+            Target::Remote { .. } => {
+                let listing = self.execute("list-accounts");
+                serde_json::from_str::<serde_json::Value>(&listing)
+                    .ok()
+                    .and_then(|listing| listing["accounts"].as_object().cloned())
+                    .map(|accounts| accounts.keys().cloned().collect())
+                    .unwrap_or_default()
+            }
+        };
+        accounts.sort();
+        accounts
+    }
+}
+
+/// Completes the word ending at `pos`: a command name for the first word and
+/// an account id after it. Returns where the word starts and the candidates.
+pub fn complete_word(line: &str, pos: usize, accounts: &[String]) -> (usize, Vec<String>) {
+    let start = line[..pos].rfind(' ').map_or(0, |space| space + 1);
+    let prefix = &line[start..pos];
+    let candidates: Vec<String> = if start == 0 {
+        COMMANDS
+            .iter()
+            .filter(|command| command.starts_with(prefix))
+            .map(|command| command.to_string())
+            .collect()
+    } else {
+        accounts
+            .iter()
+            .filter(|account| account.starts_with(prefix))
+            .cloned()
+            .collect()
+    };
+    (start, candidates)
+}
+
+struct ReplHelper {
+    accounts: Vec<String>,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete_word(line, pos, &self.accounts))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+/// Reads commands until `exit` or end of input. History is kept in
+/// `history_path` across sessions when given.
+pub fn run(mut target: Target, history_path: Option<&Path>) -> rustyline::Result<()> {
+    let mut editor: Editor<ReplHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(ReplHelper {
+        accounts: target.accounts(),
+    }));
+    if let Some(path) = history_path {
+        // A missing history file just means a first session
+        let _ = editor.load_history(path);
+    }
+
+    loop {
+        let line = match editor.readline("b> ") {
+            Ok(line) => line,
+            // Ctrl-C abandons the current line, Ctrl-D leaves
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e),
+        };
+        let command = line.trim();
+        if command.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(command)?;
+        match command {
+            "exit" | "quit" => break,
+            "help" => println!("Commands: {}", COMMANDS.join(", ")),
+            _ => println!("{}", target.execute(command)),
+        }
+        if let Some(helper) = editor.helper_mut() {
+            helper.accounts = target.accounts();
+        }
+    }
+
+    if let Some(path) = history_path {
+        editor.save_history(path)?;
+    }
+    Ok(())
+}
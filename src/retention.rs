@@ -0,0 +1,95 @@
+//! How much of the chain a node keeps: state snapshots and block pruning.
+//!
+//! An archive node keeps every block. A pruned node drops the transactions
+//! and receipts of blocks older than its retention window, but keeps their
+//! headers so the chain still links back to genesis. Either kind of node can
+//! write a snapshot of its ledger every few blocks. A pruned node never prunes
+//! a block above its latest snapshot, and nothing at all without one, so that
+//! snapshot plus the blocks kept after it are always enough to rebuild the
+//! ledger.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Snapshots kept on disk. Older ones are removed as new ones are written.
+pub const SNAPSHOTS_KEPT: usize = 2;
+
+/// Which blocks a node keeps whole.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NodeMode {
+    /// Every block, as mined.
+    #[default]
+    Archive,
+    /// Only the blocks in the retention window, and headers of older ones.
+    Pruned,
+}
+
+impl FromStr for NodeMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "archive" => Ok(NodeMode::Archive),
+            "pruned" => Ok(NodeMode::Pruned),
+            _ => Err(format!("unknown node mode '{}'", mode)),
+        }
+    }
+}
+
+impl fmt::Display for NodeMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            NodeMode::Archive => "archive",
+            NodeMode::Pruned => "pruned",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Retention {
+    pub mode: NodeMode,
+    /// Newest blocks a pruned node keeps whole.
+    pub keep_blocks: u64,
+    /// Snapshots are written at every multiple of this height. None disables
+    /// them.
+    pub snapshot_interval: Option<u64>,
+    /// Newest blocks whose undo data is kept, which bounds how far the chain
+    /// can be rewound. Final blocks lose theirs sooner.
+    pub undo_depth: u64,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention {
+            mode: NodeMode::Archive,
+            keep_blocks: 1000,
+            snapshot_interval: None,
+            undo_depth: 1000,
+        }
+    }
+}
+
+impl Retention {
+    /// Whether a snapshot is due once the block at `height` is mined.
+    pub fn is_snapshot_height(&self, height: u64) -> bool {
+        self.snapshot_interval
+            .is_some_and(|interval| interval > 0 && height.is_multiple_of(interval))
+    }
+
+    /// Highest block whose body may be pruned, given the chain's tip and the
+    /// latest snapshot. Genesis has no body, so 0 means nothing to prune,
+    /// which is also the case until a snapshot is written.
+    pub fn prune_height(&self, tip: u64, latest_snapshot: Option<u64>) -> u64 {
+        if self.mode == NodeMode::Archive {
+            return 0;
+        }
+        let outside_window = tip.saturating_sub(self.keep_blocks);
+        outside_window.min(latest_snapshot.unwrap_or(0))
+    }
+
+    /// Highest block whose undo data may be dropped, given the chain's tip and
+    /// its newest final block, which is never rewound.
+    pub fn undo_floor(&self, tip: u64, finalized: u64) -> u64 {
+        tip.saturating_sub(self.undo_depth).max(finalized)
+    }
+}
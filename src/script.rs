@@ -0,0 +1,223 @@
+//! Batch execution of command scripts with assertions, used by `b run-script`.
+//!
+//! A script holds one node command per line, run in order against a
+//! [`Target`]. On top of the node commands it understands:
+//!
+//! - `# comment` lines and blank lines, which are skipped;
+//! - `let name = value`, after which `$name` is replaced by `value`;
+//! - `expect <command> <op> <number>`, which runs a query command and compares
+//!   the last number of its response, e.g. `expect balance alice == 800`. The
+//!   operators are `==`, `!=`, `<`, `<=`, `>` and `>=`;
+//! - `expect error <code>`, which checks that the previous command failed with
+//!   that error code.
+//!
+//! `mine` and `advance-time <seconds>` are ordinary node commands. The first
+//! failed assertion stops the run.
+
+use crate::error::Error;
+use crate::repl::Target;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ScriptError {
+    #[error("line {line}: {message}")]
+    Syntax { line: usize, message: String },
+    #[error("line {line}: expected {expectation}, got {actual}")]
+    AssertionFailed {
+        line: usize,
+        expectation: String,
+        actual: String,
+    },
+    #[error("Failed to read or write the script")]
+    Io(#[from] io::Error),
+}
+
+impl ScriptError {
+    /// Script line the error refers to, 1-based.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            ScriptError::Syntax { line, .. } | ScriptError::AssertionFailed { line, .. } => {
+                Some(*line)
+            }
+            ScriptError::Io(_) => None,
+        }
+    }
+}
+
+/// Counts of what a successful run executed.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ScriptSummary {
+    pub commands: usize,
+    pub assertions: usize,
+}
+
+/// Runs the script read from `reader` against `target`, echoing each command
+/// and its response to `writer`.
+pub fn run<R: BufRead, W: Write>(
+    target: &mut Target,
+    reader: R,
+    mut writer: W,
+) -> Result<ScriptSummary, ScriptError> {
+    let mut variables: HashMap<String, String> = HashMap::new();
+    let mut summary = ScriptSummary::default();
+    let mut last_response = String::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = substitute(line, &variables, line_number)?;
+
+        if let Some(definition) = line.strip_prefix("let ") {
+            let (name, value) = parse_definition(definition, line_number)?;
+            variables.insert(name, value);
+            continue;
+        }
+
+        if let Some(assertion) = line.strip_prefix("expect ") {
+            summary.assertions += 1;
+            check_assertion(target, assertion, &last_response, line_number, &mut writer)?;
+            continue;
+        }
+
+        writeln!(writer, "> {}", line)?;
+        last_response = target.execute(&line);
+        writeln!(writer, "{}", last_response)?;
+        summary.commands += 1;
+    }
+    Ok(summary)
+}
+
+fn parse_definition(definition: &str, line: usize) -> Result<(String, String), ScriptError> {
+    let syntax_error = || ScriptError::Syntax {
+        line,
+        message: "expected `let <name> = <value>`".to_string(),
+    };
+    let (name, value) = definition.split_once('=').ok_or_else(syntax_error)?;
+    let name = name.trim();
+    let valid_name = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid_name {
+        return Err(syntax_error());
+    }
+    Ok((name.to_string(), value.trim().to_string()))
+}
+
+/// Replaces every `$name` in `line` with the value of the variable.
+fn substitute(
+    line: &str,
+    variables: &HashMap<String, String>,
+    line_number: usize,
+) -> Result<String, ScriptError> {
+    let words: Result<Vec<String>, ScriptError> = line
+        .split_whitespace()
+        .map(|word| match word.strip_prefix('$') {
+            Some(name) => variables
+                .get(name)
+                .cloned()
+                .ok_or_else(|| ScriptError::Syntax {
+                    line: line_number,
+                    message: format!("undefined variable ${}", name),
+                }),
+            None => Ok(word.to_string()),
+        })
+        .collect();
+    Ok(words?.join(" "))
+}
+
+fn check_assertion<W: Write>(
+    target: &mut Target,
+    assertion: &str,
+    last_response: &str,
+    line: usize,
+    writer: &mut W,
+) -> Result<(), ScriptError> {
+    if let Some(code) = assertion.strip_prefix("error ") {
+        let code = code.trim();
+        let actual = error_code(last_response).unwrap_or("success");
+        if actual != code {
+            return Err(ScriptError::AssertionFailed {
+                line,
+                expectation: format!("error {}", code),
+                actual: actual.to_string(),
+            });
+        }
+        writeln!(writer, "ok: error {}", code)?;
+        return Ok(());
+    }
+
+    let words: Vec<&str> = assertion.split_whitespace().collect();
+    let [command @ .., op, expected] = words.as_slice() else {
+        return Err(ScriptError::Syntax {
+            line,
+            message: "expected `expect <command> <op> <number>`".to_string(),
+        });
+    };
+    let compare: fn(&u64, &u64) -> bool = match *op {
+        "==" => u64::eq,
+        "!=" => u64::ne,
+        "<" => u64::lt,
+        "<=" => u64::le,
+        ">" => u64::gt,
+        ">=" => u64::ge,
+        _ => {
+            return Err(ScriptError::Syntax {
+                line,
+                message: format!("unknown operator `{}`", op),
+            })
+        }
+    };
+    let expected: u64 = expected.parse().map_err(|_| ScriptError::Syntax {
+        line,
+        message: format!("`{}` is not a number", expected),
+    })?;
+    if command.is_empty() {
+        return Err(ScriptError::Syntax {
+            line,
+            message: "missing command to check".to_string(),
+        });
+    }
+
+    let command = command.join(" ");
+    let response = target.execute(&command);
+    let expectation = format!("{} {} {}", command, op, expected);
+    match last_number(&response) {
+        Some(actual) if compare(&actual, &expected) => {
+            writeln!(writer, "ok: {}", expectation)?;
+            Ok(())
+        }
+        Some(actual) => Err(ScriptError::AssertionFailed {
+            line,
+            expectation,
+            actual: actual.to_string(),
+        }),
+        None => Err(ScriptError::AssertionFailed {
+            line,
+            expectation,
+            actual: response,
+        }),
+    }
+}
+
+/// Code of a wire error response, e.g. `insufficient_funds`.
+fn error_code(response: &str) -> Option<&str> {
+    Error::parse_wire(response).map(|(code, _)| code)
+}
+
+/// Last unsigned integer appearing in `response`, if it is not an error.
+fn last_number(response: &str) -> Option<u64> {
+    if error_code(response).is_some() {
+        return None;
+    }
+    response
+        .split(|c: char| !c.is_ascii_digit())
+        .rfind(|digits| !digits.is_empty())
+        .and_then(|digits| digits.parse().ok())
+}
@@ -1,28 +1,399 @@
+use crate::asset::{Asset, NATIVE_ASSET};
+use crate::encoding::{self, Decode};
+use crate::error::{ConsensusError, StorageError};
+use crate::history::HistoryEntry;
+use crate::migration::{self, MigrationReport};
+use crate::undo::BlockUndo;
+use crate::{Block, BlockHeader};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{File, OpenOptions};
-use std::io::{BufReader, BufWriter};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+pub const DEFAULT_PATH: &str = "blockchain.db";
 
 #[derive(Serialize, Deserialize, Default)]
 pub struct Storage {
+    /// Native coin balances.
     pub accounts: HashMap<String, u64>,
+    /// Balances of issued assets, keyed by account and then by asset symbol.
+    #[serde(default)]
+    pub asset_balances: HashMap<String, HashMap<String, u64>>,
+    /// Issued assets by symbol. The native coin is not listed here.
+    #[serde(default)]
+    pub assets: HashMap<String, Asset>,
+    /// Number of transfers executed from each account.
+    #[serde(default)]
+    pub nonces: HashMap<String, u64>,
+    /// Transactions that changed each account's balance, oldest first.
+    #[serde(default)]
+    pub history: HashMap<String, Vec<HistoryEntry>>,
+    /// Total amount of native coin ever brought into existence.
+    #[serde(default)]
+    pub minted: u64,
+    /// Total amount of native coin ever destroyed.
+    #[serde(default)]
+    pub burned: u64,
+    /// Undo data of each mined block, by height.
+    #[serde(default)]
+    pub undo: BTreeMap<u64, BlockUndo>,
+    /// Height of the last block applied to this ledger. Unset in ledgers
+    /// written before it was recorded, which are taken to match the block log.
+    #[serde(default)]
+    pub height: Option<u64>,
+    /// File backing this storage. In-memory storage (`None`) is never written.
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
 }
 
 impl Storage {
-    pub fn load() -> Result<Self, String> {
-        let file = File::open("blockchain.db").map_err(|e| e.to_string())?;
-        let reader = BufReader::new(file);
-        serde_json::from_reader(reader).map_err(|e| e.to_string())
+    pub fn load() -> Result<Self, StorageError> {
+        Self::load_from(DEFAULT_PATH)
+    }
+
+    /// Loads the ledger at `path`. Files in an older format are migrated in
+    /// memory; the file itself is upgraded by the next `save`.
+    pub fn load_from<P: AsRef<Path>>(path: P) -> Result<Self, StorageError> {
+        let path = path.as_ref();
+        let mut ledger = read_ledger(path)?;
+        migration::migrate(&mut ledger)?;
+        let mut storage: Storage = serde_json::from_value(ledger)?;
+        storage.path = Some(path.to_path_buf());
+        Ok(storage)
+    }
+
+    /// Upgrades the ledger at `path` to the current format and reports what
+    /// changed. The original file is kept as `<path>.v<version>.bak`. With
+    /// `dry_run` nothing is written.
+    pub fn migrate_file<P: AsRef<Path>>(
+        path: P,
+        dry_run: bool,
+    ) -> Result<MigrationReport, StorageError> {
+        let path = path.as_ref();
+        let mut ledger = read_ledger(path)?;
+        let report = migration::migrate(&mut ledger)?;
+        if dry_run || report.is_up_to_date() {
+            return Ok(report);
+        }
+        let storage = Self::load_from(path)?;
+        let backup = backup_path(path, report.from);
+        std::fs::copy(path, &backup).map_err(|source| StorageError::Io {
+            path: backup,
+            source,
+        })?;
+        storage.save()?;
+        Ok(report)
+    }
+
+    /// Loads the storage at `path`. If the file does not exist, falls back to
+    /// the newest readable snapshot next to it, and else starts an empty
+    /// ledger backed by that file. Any other failure is returned, so a ledger
+    /// that exists but cannot be read is never overwritten.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StorageError> {
+        let path = path.as_ref();
+        let mut storage = match Self::load_from(path) {
+            Ok(storage) => storage,
+            Err(StorageError::Io { source, .. }) if source.kind() == io::ErrorKind::NotFound => {
+                let snapshot = snapshot_heights(path).into_iter().rev().find_map(|height| {
+                    let snapshot = Self::load_from(snapshot_path(path, height)).ok()?;
+                    warn!(height, "Ledger missing, restored a snapshot");
+                    Some(snapshot)
+                });
+                snapshot.unwrap_or_default()
+            }
+            Err(e) => return Err(e),
+        };
+        storage.path = Some(path.to_path_buf());
+        Ok(storage)
+    }
+
+    /// Native coin in circulation.
+    pub fn total_supply(&self) -> Result<u64, ConsensusError> {
+        let native = self
+            .asset(NATIVE_ASSET)
+            .expect("the native coin always exists");
+        self.supply(&native)
+    }
+
+    /// Units of `asset` in circulation. A ledger recording more burned than
+    /// minted is reported as a supply mismatch.
+    pub fn supply(&self, asset: &Asset) -> Result<u64, ConsensusError> {
+        asset
+            .supply()
+            .ok_or_else(|| ConsensusError::SupplyMismatch {
+                asset: asset.symbol.clone(),
+                minted: asset.minted,
+                burned: asset.burned,
+                balances: self.balances_total(&asset.symbol),
+            })
+    }
+
+    /// Sum of every account's balance in `symbol`.
+    fn balances_total(&self, symbol: &str) -> u128 {
+        if symbol == NATIVE_ASSET {
+            return self.accounts.values().map(|&balance| balance as u128).sum();
+        }
+        self.asset_balances
+            .values()
+            .filter_map(|balances| balances.get(symbol))
+            .map(|&balance| balance as u128)
+            .sum()
+    }
+
+    /// Metadata of `symbol`, including the native coin.
+    pub fn asset(&self, symbol: &str) -> Option<Asset> {
+        if symbol == NATIVE_ASSET {
+            return Some(Asset {
+                symbol: NATIVE_ASSET.to_string(),
+                decimals: 0,
+                issuer: None,
+                minted: self.minted,
+                burned: self.burned,
+            });
+        }
+        self.assets.get(symbol).cloned()
     }
 
-    pub fn save(&self) -> Result<(), String> {
+    /// Balance of `account` in `asset`, zero when it holds none.
+    pub fn balance_of(&self, account: &str, asset: &str) -> u64 {
+        if asset == NATIVE_ASSET {
+            return self.accounts.get(account).copied().unwrap_or(0);
+        }
+        self.asset_balances
+            .get(account)
+            .and_then(|balances| balances.get(asset))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn set_balance(&mut self, account: &str, asset: &str, amount: u64) {
+        if asset == NATIVE_ASSET {
+            self.accounts.insert(account.to_string(), amount);
+            return;
+        }
+        self.asset_balances
+            .entry(account.to_string())
+            .or_default()
+            .insert(asset.to_string(), amount);
+    }
+
+    /// All non-zero balances of `account`, native coin included, by symbol.
+    pub fn balances(&self, account: &str) -> BTreeMap<String, u64> {
+        let mut balances: BTreeMap<String, u64> = self
+            .asset_balances
+            .get(account)
+            .into_iter()
+            .flatten()
+            .filter(|(_, &amount)| amount > 0)
+            .map(|(asset, &amount)| (asset.clone(), amount))
+            .collect();
+        if let Some(&native) = self.accounts.get(account) {
+            balances.insert(NATIVE_ASSET.to_string(), native);
+        }
+        balances
+    }
+
+    /// Checks that, for every asset, the balances add up to the minted supply minus burns.
+    pub fn check_invariants(&self) -> Result<(), ConsensusError> {
+        let mut totals: HashMap<&str, u128> = HashMap::new();
+        totals.insert(
+            NATIVE_ASSET,
+            self.accounts.values().map(|&balance| balance as u128).sum(),
+        );
+        for (asset, amount) in self.asset_balances.values().flatten() {
+            *totals.entry(asset.as_str()).or_default() += *amount as u128;
+        }
+
+        let mut symbols: Vec<&str> = self.assets.keys().map(String::as_str).collect();
+        symbols.push(NATIVE_ASSET);
+        // Balances in an asset nobody issued are a violation as well
+        symbols.extend(totals.keys().filter(|symbol| self.asset(symbol).is_none()));
+        for symbol in symbols {
+            let (minted, burned) = self
+                .asset(symbol)
+                .map_or((0, 0), |asset| (asset.minted, asset.burned));
+            let balances = totals.get(symbol).copied().unwrap_or(0);
+            let supply = minted.checked_sub(burned);
+            if supply.map(u128::from) != Some(balances) {
+                return Err(ConsensusError::SupplyMismatch {
+                    asset: symbol.to_string(),
+                    minted,
+                    burned,
+                    balances,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<(), StorageError> {
+        match &self.path {
+            Some(path) => self.write_ledger(path),
+            None => Ok(()),
+        }
+    }
+
+    fn write_ledger(&self, path: &Path) -> Result<(), StorageError> {
         let file = OpenOptions::new()
             .write(true)
             .create(true)
             .truncate(true)
-            .open("blockchain.db")
-            .map_err(|e| e.to_string())?;
-        let writer = BufWriter::new(file);
-        serde_json::to_writer(writer, self).map_err(|e| e.to_string())
+            .open(path)
+            .map_err(|source| StorageError::Io {
+                path: path.to_path_buf(),
+                source,
+            })?;
+        let mut ledger = serde_json::to_value(self)?;
+        ledger["version"] = migration::CURRENT_VERSION.into();
+        serde_json::to_writer(BufWriter::new(file), &ledger)?;
+        Ok(())
+    }
+
+    /// Copies the ledger to a snapshot for `height`, e.g.
+    /// `blockchain.snapshot.1000`.
+    pub fn write_snapshot(&self, height: u64) -> Result<(), StorageError> {
+        match &self.path {
+            Some(path) => self.write_ledger(&snapshot_path(path, height)),
+            None => Ok(()),
+        }
+    }
+
+    /// Heights of the snapshots on disk, oldest first.
+    pub fn snapshots(&self) -> Vec<u64> {
+        self.path
+            .as_deref()
+            .map(snapshot_heights)
+            .unwrap_or_default()
     }
+
+    pub fn remove_snapshot(&self, height: u64) -> Result<(), StorageError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let path = snapshot_path(path, height);
+        std::fs::remove_file(&path).map_err(|source| StorageError::Io { path, source })
+    }
+
+    /// Block log kept next to the state file, e.g. `blockchain.blocks`.
+    fn blocks_path(&self) -> Option<PathBuf> {
+        self.path.as_ref().map(|path| path.with_extension("blocks"))
+    }
+
+    /// Appends a mined block to the block log, in the binary encoding.
+    pub fn append_block(&self, block: &Block) -> Result<(), StorageError> {
+        let Some(path) = self.blocks_path() else {
+            return Ok(());
+        };
+        let mut frame = Vec::new();
+        encoding::write_frame(block, &mut frame);
+        OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(&frame))
+            .map_err(|source| StorageError::Io { path, source })
+    }
+
+    /// Headers of pruned blocks, kept next to the state file, e.g.
+    /// `blockchain.headers`.
+    fn headers_path(&self) -> Option<PathBuf> {
+        self.path
+            .as_ref()
+            .map(|path| path.with_extension("headers"))
+    }
+
+    /// Appends the headers of newly pruned blocks, in the binary encoding.
+    pub fn append_headers(&self, headers: &[BlockHeader]) -> Result<(), StorageError> {
+        let Some(path) = self.headers_path() else {
+            return Ok(());
+        };
+        let mut frames = Vec::new();
+        for header in headers {
+            encoding::write_frame(header, &mut frames);
+        }
+        OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(&frames))
+            .map_err(|source| StorageError::Io { path, source })
+    }
+
+    /// Headers of the pruned blocks, oldest first, starting at height 1.
+    pub fn load_headers(&self) -> Result<Vec<BlockHeader>, StorageError> {
+        read_log(self.headers_path())
+    }
+
+    /// Replaces the block log with `blocks`, after the chain was rewound or
+    /// pruned.
+    pub fn rewrite_blocks(&self, blocks: &[Block]) -> Result<(), StorageError> {
+        let Some(path) = self.blocks_path() else {
+            return Ok(());
+        };
+        let mut frames = Vec::new();
+        for block in blocks {
+            encoding::write_frame(block, &mut frames);
+        }
+        std::fs::write(&path, frames).map_err(|source| StorageError::Io { path, source })
+    }
+
+    /// Blocks in the block log, oldest first. The genesis block and pruned
+    /// blocks are not stored.
+    pub fn load_blocks(&self) -> Result<Vec<Block>, StorageError> {
+        read_log(self.blocks_path())
+    }
+}
+
+fn read_log<T: Decode>(path: Option<PathBuf>) -> Result<Vec<T>, StorageError> {
+    let Some(path) = path else {
+        return Ok(Vec::new());
+    };
+    match std::fs::read(&path) {
+        Ok(bytes) => Ok(encoding::read_frames(&bytes)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(source) => Err(StorageError::Io { path, source }),
+    }
+}
+
+fn snapshot_path(path: &Path, height: u64) -> PathBuf {
+    path.with_extension(format!("snapshot.{}", height))
+}
+
+/// Heights of the snapshots of the ledger at `path`, oldest first.
+fn snapshot_heights(path: &Path) -> Vec<u64> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+        return Vec::new();
+    };
+    let prefix = format!("{}.snapshot.", stem);
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut heights: Vec<u64> = entries
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name();
+            name.to_str()?.strip_prefix(&prefix)?.parse().ok()
+        })
+        .collect();
+    heights.sort_unstable();
+    heights
+}
+
+fn read_ledger(path: &Path) -> Result<serde_json::Value, StorageError> {
+    let file = File::open(path).map_err(|source| StorageError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    Ok(serde_json::from_reader(BufReader::new(file))?)
+}
+
+fn backup_path(path: &Path, version: u64) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".v{}.bak", version));
+    PathBuf::from(backup)
 }
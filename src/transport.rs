@@ -0,0 +1,264 @@
+//! Encrypted sessions on the node's port, using the Noise `XX` handshake.
+//!
+//! Both ends hold a static Curve25519 key. A client opens an encrypted session
+//! by sending `HANDSHAKE_TAG` as its first byte, which neither text commands
+//! nor binary messages start with, and then runs the handshake. Afterwards the
+//! request and the response each travel as encrypted frames: a 2-byte
+//! big-endian length, then the ciphertext. An empty frame ends a message, so
+//! a truncated message is never mistaken for a complete one.
+//!
+//! The handshake reveals each side's static key to the other. A client that
+//! knows the node's public key checks it, which authenticates the node.
+
+use crate::error::{NetworkError, Result, StorageError};
+use snow::params::DHChoice;
+use snow::resolvers::{CryptoResolver, DefaultResolver};
+use snow::{Builder, HandshakeState, TransportState};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+
+/// Handshake pattern and primitives of every encrypted session.
+pub const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+
+/// First byte of a session that starts with a handshake.
+pub const HANDSHAKE_TAG: u8 = 0;
+
+/// Largest Noise message, including the 16-byte authentication tag.
+const MAX_FRAME: usize = 65535;
+const TAG_LEN: usize = 16;
+
+/// A static Curve25519 key pair identifying a node or client.
+#[derive(Clone, PartialEq)]
+pub struct StaticKey {
+    private: Vec<u8>,
+    public: Vec<u8>,
+}
+
+impl StaticKey {
+    pub fn generate() -> Self {
+        let keypair = builder()
+            .generate_keypair()
+            .expect("Failed to generate a static key");
+        StaticKey {
+            private: keypair.private,
+            public: keypair.public,
+        }
+    }
+
+    /// Rebuilds the key pair from a hex encoded private key.
+    pub fn from_private_hex(private: &str) -> Option<Self> {
+        let private = from_hex(private).filter(|key| key.len() == 32)?;
+        let mut dh = DefaultResolver.resolve_dh(&DHChoice::Curve25519)?;
+        dh.set(&private);
+        Some(StaticKey {
+            public: dh.pubkey().to_vec(),
+            private,
+        })
+    }
+
+    /// Reads the key stored at `path`, creating a new one there, readable by
+    /// its owner only, if the file does not exist yet. A key file that other
+    /// users can access is refused.
+    pub fn load_or_generate<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let io_error = |source| StorageError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        if !path.exists() {
+            let key = StaticKey::generate();
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            let mut file = options.open(path).map_err(io_error)?;
+            writeln!(file, "{}", to_hex(&key.private)).map_err(io_error)?;
+            return Ok(key);
+        }
+        // Anyone who can read the private key can pose as this node
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(path).map_err(io_error)?.permissions().mode();
+            if mode & 0o077 != 0 {
+                return Err(StorageError::KeyExposed(path.to_path_buf()).into());
+            }
+        }
+        let contents = fs::read_to_string(path).map_err(io_error)?;
+        StaticKey::from_private_hex(contents.trim()).ok_or_else(|| {
+            StorageError::Serialization(serde::de::Error::custom(
+                "expected a hex encoded 32-byte private key",
+            ))
+            .into()
+        })
+    }
+
+    pub fn public(&self) -> &[u8] {
+        &self.public
+    }
+
+    /// Public key, hex encoded, as given to clients that pin it.
+    pub fn public_hex(&self) -> String {
+        to_hex(&self.public)
+    }
+}
+
+impl std::fmt::Debug for StaticKey {
+    // Keeps the private half out of logs
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StaticKey")
+            .field("public", &self.public_hex())
+            .finish_non_exhaustive()
+    }
+}
+
+/// An established encrypted session over a TCP stream, or any other byte
+/// stream.
+pub struct SecureStream<S = TcpStream> {
+    stream: S,
+    noise: TransportState,
+    max_message_len: usize,
+}
+
+impl<S: Read + Write> SecureStream<S> {
+    /// Opens a session as the client. With `expected`, the handshake fails
+    /// unless the node's static key is that public key.
+    pub fn connect(
+        mut stream: S,
+        local: &StaticKey,
+        expected: Option<&[u8]>,
+    ) -> Result<Self, NetworkError> {
+        stream.write_all(&[HANDSHAKE_TAG])?;
+        let mut noise = builder()
+            .local_private_key(&local.private)
+            .build_initiator()?;
+        // -> e
+        write_handshake(&mut stream, &mut noise)?;
+        // <- e, ee, s, es
+        read_handshake(&mut stream, &mut noise)?;
+        if let Some(expected) = expected {
+            let actual = noise.get_remote_static().unwrap_or_default();
+            if actual != expected {
+                return Err(NetworkError::KeyMismatch {
+                    expected: to_hex(expected),
+                    actual: to_hex(actual),
+                });
+            }
+        }
+        // -> s, se
+        write_handshake(&mut stream, &mut noise)?;
+        Ok(SecureStream {
+            stream,
+            noise: noise.into_transport_mode()?,
+            max_message_len: usize::MAX,
+        })
+    }
+
+    /// Answers the handshake of a client whose `HANDSHAKE_TAG` was read.
+    pub fn accept(mut stream: S, local: &StaticKey) -> Result<Self, NetworkError> {
+        let mut noise = builder()
+            .local_private_key(&local.private)
+            .build_responder()?;
+        read_handshake(&mut stream, &mut noise)?;
+        write_handshake(&mut stream, &mut noise)?;
+        read_handshake(&mut stream, &mut noise)?;
+        Ok(SecureStream {
+            stream,
+            noise: noise.into_transport_mode()?,
+            max_message_len: usize::MAX,
+        })
+    }
+
+    /// Static public key of the other end.
+    pub fn remote_key(&self) -> &[u8] {
+        self.noise.get_remote_static().unwrap_or_default()
+    }
+
+    /// Encrypts and sends one message.
+    pub fn send(&mut self, message: &[u8]) -> Result<(), NetworkError> {
+        let mut frame = vec![0; MAX_FRAME];
+        for chunk in message.chunks(MAX_FRAME - TAG_LEN) {
+            let len = self.noise.write_message(chunk, &mut frame)?;
+            write_frame(&mut self.stream, &frame[..len])?;
+        }
+        let len = self.noise.write_message(&[], &mut frame)?;
+        write_frame(&mut self.stream, &frame[..len])?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Makes `receive` refuse messages longer than `max_len` bytes.
+    pub fn limit_messages(&mut self, max_len: usize) {
+        self.max_message_len = max_len;
+    }
+
+    /// Receives and decrypts one message.
+    pub fn receive(&mut self) -> Result<Vec<u8>, NetworkError> {
+        let mut message = Vec::new();
+        let mut plaintext = vec![0; MAX_FRAME];
+        loop {
+            let frame = read_frame(&mut self.stream)?;
+            let len = self.noise.read_message(&frame, &mut plaintext)?;
+            if len == 0 {
+                return Ok(message);
+            }
+            if message.len() + len > self.max_message_len {
+                return Err(NetworkError::RequestTooLarge {
+                    max: self.max_message_len,
+                });
+            }
+            message.extend_from_slice(&plaintext[..len]);
+        }
+    }
+}
+
+fn builder() -> Builder<'static> {
+    Builder::new(NOISE_PARAMS.parse().expect("Noise parameters are valid"))
+}
+
+fn write_handshake(
+    stream: &mut impl Write,
+    noise: &mut HandshakeState,
+) -> Result<(), NetworkError> {
+    let mut frame = vec![0; MAX_FRAME];
+    let len = noise.write_message(&[], &mut frame)?;
+    write_frame(stream, &frame[..len])
+}
+
+fn read_handshake(stream: &mut impl Read, noise: &mut HandshakeState) -> Result<(), NetworkError> {
+    let frame = read_frame(stream)?;
+    let mut payload = vec![0; MAX_FRAME];
+    noise.read_message(&frame, &mut payload)?;
+    Ok(())
+}
+
+fn write_frame(stream: &mut impl Write, frame: &[u8]) -> Result<(), NetworkError> {
+    let len = u16::try_from(frame.len()).expect("Noise frames fit in 64 KiB");
+    stream.write_all(&len.to_be_bytes())?;
+    stream.write_all(frame)?;
+    Ok(())
+}
+
+fn read_frame(stream: &mut impl Read) -> Result<Vec<u8>, NetworkError> {
+    let mut len = [0; 2];
+    stream.read_exact(&mut len)?;
+    let mut frame = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut frame)?;
+    Ok(frame)
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,83 @@
+//! Undo data: the ledger entries a block overwrote, so the block can be taken
+//! off the chain again.
+
+use crate::asset::Asset;
+use crate::storage::Storage;
+use crate::Transaction;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// An account's entries before a block touched them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AccountUndo {
+    pub balance: Option<u64>,
+    pub asset_balances: Option<HashMap<String, u64>>,
+    pub nonce: Option<u64>,
+    /// History is append-only, so its length is enough to restore it.
+    pub history_len: usize,
+}
+
+/// Ledger state a block overwrote.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BlockUndo {
+    pub accounts: BTreeMap<String, AccountUndo>,
+    pub assets: BTreeMap<String, Option<Asset>>,
+    pub minted: u64,
+    pub burned: u64,
+}
+
+impl BlockUndo {
+    /// Records the entries `transactions` may change, before they are applied.
+    pub fn capture(storage: &Storage, transactions: &[Transaction]) -> Self {
+        let accounts = transactions
+            .iter()
+            .flat_map(Transaction::accounts)
+            .map(|account| {
+                let undo = AccountUndo {
+                    balance: storage.accounts.get(account).copied(),
+                    asset_balances: storage.asset_balances.get(account).cloned(),
+                    nonce: storage.nonces.get(account).copied(),
+                    history_len: storage.history.get(account).map_or(0, Vec::len),
+                };
+                (account.to_string(), undo)
+            })
+            .collect();
+        let assets = transactions
+            .iter()
+            .filter_map(Transaction::asset)
+            .map(|symbol| (symbol.to_string(), storage.assets.get(symbol).cloned()))
+            .collect();
+        BlockUndo {
+            accounts,
+            assets,
+            minted: storage.minted,
+            burned: storage.burned,
+        }
+    }
+
+    /// Puts the recorded entries back into `storage`.
+    pub fn restore(self, storage: &mut Storage) {
+        for (account, undo) in self.accounts {
+            restore_entry(&mut storage.accounts, &account, undo.balance);
+            restore_entry(&mut storage.asset_balances, &account, undo.asset_balances);
+            restore_entry(&mut storage.nonces, &account, undo.nonce);
+            if undo.history_len == 0 {
+                storage.history.remove(&account);
+            } else if let Some(history) = storage.history.get_mut(&account) {
+                history.truncate(undo.history_len);
+            }
+        }
+        for (symbol, asset) in self.assets {
+            restore_entry(&mut storage.assets, &symbol, asset);
+        }
+        storage.minted = self.minted;
+        storage.burned = self.burned;
+    }
+}
+
+fn restore_entry<T>(map: &mut HashMap<String, T>, key: &str, value: Option<T>) {
+    match value {
+        Some(value) => map.insert(key.to_string(), value),
+        None => map.remove(key),
+    };
+}
@@ -1,6 +1,4 @@
-use blockchain_simulation::Blockchain;
 use blockchain_simulation::Transaction;
-use std::collections::HashMap;
 use std::io::{BufRead, BufReader, Read, Result};
 use std::time::Duration;
 struct MockStdin {
@@ -44,25 +42,30 @@ impl BufRead for MockStdin {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use blockchain_simulation::encoding;
+    use blockchain_simulation::error::{
+        DecodeError, Error, NotFoundError, StorageError, ValidationError,
+    };
+    use blockchain_simulation::genesis::GenesisConfig;
+    use blockchain_simulation::history::Direction;
+    use blockchain_simulation::message::Message;
+    use blockchain_simulation::receipt::{ReceiptStatus, TxStatus};
     use blockchain_simulation::storage::Storage;
-    use blockchain_simulation::Blockchain;
+    use blockchain_simulation::{Block, Blockchain};
     fn create_clean_blockchain() -> Blockchain {
-        let clean_storage = Storage {
-            accounts: HashMap::new(),
-        };
         let mut blockchain = Blockchain::new(Duration::from_secs(1));
         blockchain.is_active = true; // Make sure the blockchain is active
-        blockchain.storage = clean_storage;
         blockchain
     }
 
     #[cfg(test)]
+    #[allow(clippy::module_inception)]
     mod tests {
         use super::*;
 
         #[test]
         fn test_blockchain_simulation() {
-            let commands = vec![
+            let commands = [
                 "b create-account alice 1000",
                 "b create-account bob 500",
                 "b transfer alice bob 200",
@@ -93,7 +96,7 @@ mod tests {
 
             // Verify the transaction is in pending_transactions
             assert!(blockchain.pending_transactions.iter().any(|tx| match tx {
-                Transaction::CreateAccount { id, balance } =>
+                Transaction::CreateAccount { id, balance, .. } =>
                     id == account_name && *balance == initial_balance,
                 _ => false,
             }));
@@ -184,14 +187,16 @@ mod tests {
             // Process the creation transactions
             blockchain.mine_block();
 
-            let accounts_list = blockchain.list_accounts();
-            assert!(
-                accounts_list.contains("Account ID: alice, Balance: 1000"),
-                "Alice should be listed with a balance of 1000"
+            let accounts: Vec<(String, u64)> = blockchain.list_accounts().into_iter().collect();
+            assert_eq!(
+                accounts,
+                [("alice".to_string(), 1000), ("bob".to_string(), 500)]
             );
-            assert!(
-                accounts_list.contains("Account ID: bob, Balance: 500"),
-                "Bob should be listed with a balance of 500"
+            let response = blockchain.process_command("list-accounts").unwrap();
+            let listing: serde_json::Value = serde_json::from_str(&response).unwrap();
+            assert_eq!(
+                listing,
+                serde_json::json!({ "accounts": { "alice": 1000, "bob": 500 } })
             );
         }
 
@@ -298,5 +303,1590 @@ mod tests {
                 "Bob's account should exist with correct balance after mining"
             );
         }
+
+        #[test]
+        fn test_process_command_block_queries() {
+            let mut blockchain = create_clean_blockchain();
+            blockchain.create_account("alice", 1000).unwrap();
+            blockchain.mine_block();
+
+            assert_eq!(
+                blockchain.process_command("height").unwrap(),
+                r#"{"height":1}"#
+            );
+
+            let by_height = blockchain.process_command("get-block 1").unwrap();
+            let block: serde_json::Value = serde_json::from_str(&by_height).unwrap();
+            assert_eq!(block["height"], 1);
+            assert_eq!(block["transactions"].as_array().unwrap().len(), 1);
+
+            let hash = block["hash"].as_str().unwrap();
+            let by_hash = blockchain
+                .process_command(&format!("get-block {}", hash))
+                .unwrap();
+            assert_eq!(by_hash, by_height, "Lookup by hash should match height");
+
+            assert!(blockchain.process_command("get-block 5").is_err());
+        }
+
+        #[test]
+        fn test_process_command_tx_and_mempool_queries() {
+            let mut blockchain = create_clean_blockchain();
+            blockchain.create_account("alice", 1000).unwrap();
+            let hash = blockchain.pending_transactions[0].hash();
+
+            let mempool: serde_json::Value =
+                serde_json::from_str(&blockchain.process_command("mempool").unwrap()).unwrap();
+            assert_eq!(mempool["size"], 1);
+            assert_eq!(mempool["transactions"][0]["hash"], hash.as_str());
+
+            let tx: serde_json::Value = serde_json::from_str(
+                &blockchain
+                    .process_command(&format!("get-tx {}", hash))
+                    .unwrap(),
+            )
+            .unwrap();
+            assert_eq!(tx["status"], "pending");
+
+            blockchain.mine_block();
+            let tx: serde_json::Value = serde_json::from_str(
+                &blockchain
+                    .process_command(&format!("get-tx {}", hash))
+                    .unwrap(),
+            )
+            .unwrap();
+            assert_eq!(tx["status"], "confirmed");
+            assert_eq!(tx["block_height"], 1);
+
+            let info: serde_json::Value =
+                serde_json::from_str(&blockchain.process_command("chain-info").unwrap()).unwrap();
+            assert_eq!(info["height"], 1);
+            assert_eq!(info["pending_transactions"], 0);
+            assert_eq!(info["accounts"], 1);
+        }
+
+        #[test]
+        fn test_transaction_ids_and_receipts() {
+            let mut blockchain = create_clean_blockchain();
+            blockchain.create_account("alice", 300).unwrap();
+            blockchain.mine_block();
+
+            // Identical transfers must still get distinct ids
+            let first = blockchain.transfer("alice", "bob", 200).unwrap();
+            let second = blockchain.transfer("alice", "bob", 200).unwrap();
+            assert_ne!(first, second);
+            assert_eq!(blockchain.tx_status(&first), Some(TxStatus::Pending));
+
+            blockchain.mine_block();
+            assert_eq!(
+                blockchain.tx_status(&first),
+                Some(TxStatus::Confirmed { height: 2 })
+            );
+            assert_eq!(
+                blockchain.tx_status(&second),
+                Some(TxStatus::Failed {
+                    height: 2,
+                    reason: "Insufficient funds".to_string()
+                })
+            );
+            assert_eq!(blockchain.balance("alice").unwrap(), 100);
+            assert_eq!(blockchain.chain[2].receipts().len(), 2);
+            assert_eq!(blockchain.tx_status("unknown"), None);
+        }
+
+        #[test]
+        fn test_resubmitted_transaction_status() {
+            let mut blockchain = create_clean_blockchain();
+            blockchain.create_account("alice", 0).unwrap();
+            blockchain.mine_block();
+            let transfer = Transaction::Transfer {
+                chain_id: "b-dev".to_string(),
+                from: "carol".to_string(),
+                to: "alice".to_string(),
+                amount: 5,
+                nonce: 0,
+            };
+
+            // Dropped without using its nonce, so the same transaction can
+            // be submitted again
+            let id = blockchain.submit_transaction(transfer.clone()).unwrap();
+            blockchain.mine_block();
+            assert!(matches!(
+                blockchain.tx_status(&id),
+                Some(TxStatus::Dropped { height: 2, .. })
+            ));
+            blockchain.create_account("carol", 10).unwrap();
+            blockchain.mine_block();
+
+            assert_eq!(blockchain.submit_transaction(transfer).unwrap(), id);
+            assert_eq!(blockchain.tx_status(&id), Some(TxStatus::Pending));
+            let tx: serde_json::Value = serde_json::from_str(
+                &blockchain
+                    .process_command(&format!("get-tx {}", id))
+                    .unwrap(),
+            )
+            .unwrap();
+            assert_eq!(tx["block_height"], serde_json::Value::Null);
+            assert_eq!(tx["receipt"], serde_json::Value::Null);
+            blockchain.mine_block();
+            assert_eq!(
+                blockchain.tx_status(&id),
+                Some(TxStatus::Confirmed { height: 4 })
+            );
+        }
+
+        #[test]
+        fn test_process_command_tx_status() {
+            let mut blockchain = create_clean_blockchain();
+            let message = blockchain
+                .process_command("create-account alice 1000")
+                .unwrap();
+            let tx_id = blockchain_simulation::client::submitted_tx_id(&message).unwrap();
+
+            assert_eq!(
+                blockchain
+                    .process_command(&format!("tx-status {}", tx_id))
+                    .unwrap(),
+                format!("Transaction {}: pending", tx_id)
+            );
+            blockchain.mine_block();
+            assert_eq!(
+                blockchain
+                    .process_command(&format!("tx-status {}", tx_id))
+                    .unwrap(),
+                format!("Transaction {}: confirmed at height 1", tx_id)
+            );
+        }
+
+        #[test]
+        fn test_account_history_index() {
+            let mut blockchain = create_clean_blockchain();
+            blockchain.create_account("alice", 1000).unwrap();
+            blockchain.create_account("bob", 500).unwrap();
+            blockchain.mine_block();
+            blockchain.transfer("alice", "bob", 200).unwrap();
+            blockchain.mine_block();
+            blockchain.transfer("bob", "alice", 50).unwrap();
+            blockchain.mine_block();
+
+            let page = blockchain.history("alice", 1, 2).unwrap();
+            assert_eq!(page.total, 3);
+            assert_eq!(page.entries.len(), 2);
+
+            // Newest first
+            let latest = &page.entries[0];
+            assert_eq!(latest.height, 3);
+            assert_eq!(latest.direction, Direction::Received);
+            assert_eq!(latest.counterparty.as_deref(), Some("bob"));
+            assert_eq!(latest.amount, 50);
+            assert_eq!(latest.balance, 850);
+
+            let sent = &page.entries[1];
+            assert_eq!(sent.direction, Direction::Sent);
+            assert_eq!(sent.balance, 800);
+
+            let last_page = blockchain.history("alice", 2, 2).unwrap();
+            assert_eq!(last_page.entries.len(), 1);
+            assert_eq!(last_page.entries[0].direction, Direction::Created);
+
+            assert!(blockchain.history("nonexistent", 1, 2).is_err());
+        }
+
+        #[test]
+        fn test_process_command_history() {
+            let mut blockchain = create_clean_blockchain();
+            blockchain.create_account("alice", 1000).unwrap();
+            blockchain.mine_block();
+            blockchain.transfer("alice", "bob", 200).unwrap();
+            blockchain.mine_block();
+
+            let page: serde_json::Value =
+                serde_json::from_str(&blockchain.process_command("history bob").unwrap()).unwrap();
+            assert_eq!(page["total"], 1);
+            assert_eq!(page["entries"][0]["direction"], "received");
+            assert_eq!(page["entries"][0]["counterparty"], "alice");
+            assert_eq!(page["entries"][0]["balance"], 200);
+
+            assert!(blockchain.process_command("history bob x").is_err());
+        }
+
+        #[test]
+        fn test_typed_errors() {
+            let mut blockchain = create_clean_blockchain();
+            blockchain.create_account("alice", 100).unwrap();
+            blockchain.mine_block();
+
+            let error = blockchain.transfer("alice", "bob", 500).unwrap_err();
+            assert!(matches!(
+                error,
+                Error::Validation(ValidationError::InsufficientFunds)
+            ));
+            assert_eq!(error.category(), "validation");
+            assert_eq!(
+                error.to_wire(),
+                "Error [insufficient_funds]: Insufficient funds"
+            );
+            assert_eq!(
+                Error::parse_wire(&error.to_wire()),
+                Some(("insufficient_funds", "Insufficient funds"))
+            );
+            assert_eq!(Error::parse_wire("Balance of 'alice': 100"), None);
+
+            let error = blockchain.balance("nonexistent").unwrap_err();
+            assert!(matches!(
+                error,
+                Error::NotFound(NotFoundError::Account(ref id)) if id == "nonexistent"
+            ));
+            assert_eq!(error.code(), "account_not_found");
+
+            let error = blockchain
+                .process_command("transfer alice bob ten")
+                .unwrap_err();
+            assert_eq!(error.code(), "invalid_number");
+            assert!(
+                std::error::Error::source(&error).is_some(),
+                "The parse error should be kept as the source"
+            );
+
+            let error = Storage::load_from("does-not-exist.db").err().unwrap();
+            assert!(matches!(error, StorageError::Io { .. }));
+            assert_eq!(error.code(), "storage_io");
+        }
+
+        #[test]
+        fn test_metrics_counters() {
+            let mut blockchain = create_clean_blockchain();
+            blockchain.create_account("alice", 100).unwrap();
+            assert!(blockchain.create_account("alice", 100).is_err());
+            assert!(blockchain.transfer("nobody", "alice", 5).is_err());
+
+            let rendered = blockchain.metrics.render();
+            assert!(rendered.contains("blockchain_transactions_admitted_total 1\n"));
+            assert!(rendered.contains("blockchain_mempool_size 1\n"));
+            assert!(rendered.contains(
+                "blockchain_transactions_rejected_total{reason=\"duplicate_transaction\"} 1\n"
+            ));
+            assert!(rendered.contains(
+                "blockchain_transactions_rejected_total{reason=\"account_not_found\"} 1\n"
+            ));
+
+            blockchain.mine_block();
+            let rendered = blockchain.metrics.render();
+            assert!(rendered.contains("blockchain_blocks_mined_total 1\n"));
+            assert!(rendered.contains("blockchain_chain_height 1\n"));
+            assert!(rendered.contains("blockchain_mempool_size 0\n"));
+            assert!(rendered.contains("blockchain_mining_duration_seconds_count 1\n"));
+        }
+
+        #[test]
+        fn test_metrics_endpoint() {
+            use std::io::{Read, Write};
+
+            let blockchain = create_clean_blockchain();
+            let addr =
+                blockchain_simulation::metrics::serve(0, blockchain.metrics.clone()).unwrap();
+
+            // A client that connects and sends nothing does not hold up scrapes
+            let _idle = std::net::TcpStream::connect(addr).unwrap();
+            let mut stream = std::net::TcpStream::connect(addr).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(1)))
+                .unwrap();
+            stream
+                .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+
+            assert!(response.starts_with("HTTP/1.1 200 OK"));
+            assert!(response.contains("# TYPE blockchain_blocks_mined_total counter"));
+        }
+
+        #[test]
+        fn test_overflow_rejected_with_receipt() {
+            let mut blockchain = create_clean_blockchain();
+            blockchain.create_account("alice", u64::MAX - 10).unwrap();
+            blockchain.mine_block();
+
+            // Rejected at admission since the supply would overflow
+            let error = blockchain.create_account("bob", 100).unwrap_err();
+            assert_eq!(error.code(), "overflow");
+
+            // A transaction that slipped into the mempool gets a failed receipt
+            blockchain
+                .pending_transactions
+                .push(Transaction::CreateAccount {
+                    chain_id: "b-dev".to_string(),
+                    id: "bob".to_string(),
+                    balance: 100,
+                });
+            let tx_id = blockchain.pending_transactions[0].hash();
+            blockchain.mine_block();
+            assert_eq!(
+                blockchain.tx_status(&tx_id),
+                Some(TxStatus::Failed {
+                    height: 2,
+                    reason: "Arithmetic overflow".to_string()
+                })
+            );
+            assert!(!blockchain.storage.accounts.contains_key("bob"));
+            assert!(blockchain.check_invariants().is_ok());
+        }
+
+        #[test]
+        fn test_total_supply_invariant() {
+            let mut blockchain = create_clean_blockchain();
+            blockchain.create_account("alice", 1000).unwrap();
+            blockchain.create_account("bob", 500).unwrap();
+            blockchain.mine_block();
+            blockchain.transfer("alice", "bob", 200).unwrap();
+            blockchain.mine_block();
+
+            assert_eq!(blockchain.storage.total_supply().unwrap(), 1500);
+            assert_eq!(
+                blockchain.process_command("check-invariants").unwrap(),
+                "Invariants hold: total supply 1500 across 2 accounts"
+            );
+
+            // Money appearing out of nowhere breaks the invariant
+            blockchain.storage.accounts.insert("mallory".to_string(), 1);
+            let error = blockchain.check_invariants().unwrap_err();
+            assert_eq!(error.code(), "supply_mismatch");
+
+            // So does burning more than was minted, which must not underflow
+            blockchain.storage.burned = blockchain.storage.minted + 1;
+            let error = blockchain.process_command("chain-info").unwrap_err();
+            assert_eq!(error.code(), "supply_mismatch");
+        }
+
+        #[test]
+        fn test_issue_and_transfer_asset() {
+            let mut blockchain = create_clean_blockchain();
+            blockchain.create_account("alice", 1000).unwrap();
+            blockchain.create_account("bob", 500).unwrap();
+            blockchain.mine_block();
+
+            blockchain.issue_asset("alice", "PTS", 2, 10_000).unwrap();
+            blockchain.mine_block();
+            assert_eq!(blockchain.asset_balance("alice", "PTS").unwrap(), 10_000);
+
+            blockchain
+                .transfer_asset("alice", "bob", "PTS", 2_500)
+                .unwrap();
+            // Native and asset transfers share the sender's nonce sequence
+            blockchain.transfer("alice", "bob", 100).unwrap();
+            blockchain.mine_block();
+
+            assert_eq!(blockchain.asset_balance("alice", "PTS").unwrap(), 7_500);
+            assert_eq!(blockchain.asset_balance("bob", "PTS").unwrap(), 2_500);
+            assert_eq!(blockchain.balance("alice").unwrap(), 900);
+            assert_eq!(blockchain.asset_balance("bob", "B").unwrap(), 600);
+
+            let balances = blockchain.balances("bob").unwrap();
+            assert_eq!(balances.get("B"), Some(&600));
+            assert_eq!(balances.get("PTS"), Some(&2_500));
+
+            let asset = blockchain.asset_info("PTS").unwrap();
+            assert_eq!(asset.decimals, 2);
+            assert_eq!(asset.issuer.as_deref(), Some("alice"));
+            assert_eq!(asset.supply(), Some(10_000));
+            assert_eq!(blockchain.asset_info("B").unwrap().supply(), Some(1500));
+            assert!(blockchain.check_invariants().is_ok());
+
+            let page = blockchain.history("bob", 1, 1).unwrap();
+            assert_eq!(page.entries[0].asset, "B");
+            let page = blockchain.history("bob", 2, 1).unwrap();
+            assert_eq!(page.entries[0].asset, "PTS");
+        }
+
+        #[test]
+        fn test_asset_validation() {
+            let mut blockchain = create_clean_blockchain();
+            blockchain.create_account("alice", 1000).unwrap();
+            blockchain.mine_block();
+
+            let error = blockchain.issue_asset("alice", "pts", 2, 100).unwrap_err();
+            assert_eq!(error.code(), "invalid_symbol");
+            let error = blockchain.issue_asset("alice", "B", 0, 100).unwrap_err();
+            assert_eq!(error.code(), "asset_exists");
+            let error = blockchain.issue_asset("nobody", "PTS", 0, 100).unwrap_err();
+            assert_eq!(error.code(), "account_not_found");
+
+            blockchain.issue_asset("alice", "PTS", 0, 100).unwrap();
+            let error = blockchain.issue_asset("alice", "PTS", 0, 100).unwrap_err();
+            assert_eq!(error.code(), "duplicate_transaction");
+            blockchain.mine_block();
+
+            let error = blockchain
+                .transfer_asset("alice", "bob", "USD", 1)
+                .unwrap_err();
+            assert_eq!(error.code(), "asset_not_found");
+            let error = blockchain
+                .transfer_asset("alice", "bob", "PTS", 101)
+                .unwrap_err();
+            assert_eq!(error.code(), "insufficient_funds");
+        }
+
+        #[test]
+        fn test_process_command_assets() {
+            let mut blockchain = create_clean_blockchain();
+            blockchain.create_account("alice", 1000).unwrap();
+            blockchain.mine_block();
+
+            blockchain
+                .process_command("issue-asset alice USDX 6 5000")
+                .unwrap();
+            blockchain.mine_block();
+            blockchain
+                .process_command("transfer-asset alice bob USDX 1200")
+                .unwrap();
+            blockchain.mine_block();
+
+            assert_eq!(
+                blockchain.process_command("balance bob USDX").unwrap(),
+                "Balance of 'bob' in USDX: 1200"
+            );
+            let info: serde_json::Value =
+                serde_json::from_str(&blockchain.process_command("asset-info USDX").unwrap())
+                    .unwrap();
+            assert_eq!(info["supply"], 5000);
+            assert_eq!(info["decimals"], 6);
+        }
+
+        fn create_closed_blockchain() -> Blockchain {
+            let genesis = GenesisConfig {
+                authority: Some("treasury".to_string()),
+                allocations: [("treasury".to_string(), 1000), ("alice".to_string(), 50)]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            };
+            let mut blockchain = Blockchain::with_genesis(genesis, Storage::default());
+            blockchain.is_active = true;
+            blockchain
+        }
+
+        #[test]
+        fn test_sub_second_block_time() {
+            let blockchain = Blockchain::new(Duration::from_millis(500));
+            assert_eq!(blockchain.block_time, Duration::from_millis(500));
+        }
+
+        #[test]
+        fn test_genesis_allocations() {
+            let blockchain = create_closed_blockchain();
+            assert_eq!(blockchain.balance("treasury").unwrap(), 1000);
+            assert_eq!(blockchain.balance("alice").unwrap(), 50);
+            assert_eq!(blockchain.storage.total_supply().unwrap(), 1050);
+            assert!(blockchain.check_invariants().is_ok());
+        }
+
+        #[test]
+        fn test_genesis_hash_uses_canonical_encoding() {
+            use sha2::{Digest, Sha256};
+
+            let config = GenesisConfig::default();
+            let expected = format!("{:x}", Sha256::digest(encoding::encode(&config)));
+            assert_eq!(config.hash(), expected);
+
+            // The JSON layout of a genesis file does not matter, its content does
+            let parse = |json: &str| serde_json::from_str::<GenesisConfig>(json).unwrap();
+            let one = parse(r#"{"chain_id": "b-x", "allocations": {"a": 1, "b": 2}}"#);
+            let other = parse(r#"{"allocations": {"b": 2, "a": 1}, "chain_id": "b-x"}"#);
+            assert_eq!(one.hash(), other.hash());
+            let mut deeper = one.clone();
+            deeper.consensus.finality_depth = Some(6);
+            assert_ne!(deeper.hash(), one.hash());
+        }
+
+        #[test]
+        fn test_mint_and_burn_native_coin() {
+            let mut blockchain = create_closed_blockchain();
+
+            let error = blockchain.create_account("bob", 100).unwrap_err();
+            assert_eq!(error.code(), "initial_balance_not_allowed");
+            blockchain.create_account("bob", 0).unwrap();
+
+            let error = blockchain.mint("alice", "bob", "B", 100).unwrap_err();
+            assert_eq!(error.code(), "unauthorized");
+
+            blockchain.mint("treasury", "bob", "B", 300).unwrap();
+            blockchain.burn("treasury", "B", 400).unwrap();
+            blockchain.mine_block();
+
+            assert_eq!(blockchain.balance("bob").unwrap(), 300);
+            assert_eq!(blockchain.balance("treasury").unwrap(), 600);
+            let native = blockchain.asset_info("B").unwrap();
+            assert_eq!(native.minted, 1350);
+            assert_eq!(native.burned, 400);
+            assert_eq!(native.supply(), Some(950));
+            assert!(blockchain.check_invariants().is_ok());
+
+            let page = blockchain.history("bob", 1, 10).unwrap();
+            assert_eq!(page.entries[0].direction, Direction::Minted);
+        }
+
+        #[test]
+        fn test_mint_restricted_to_asset_issuer() {
+            let mut blockchain = create_clean_blockchain();
+            blockchain.create_account("alice", 10).unwrap();
+            blockchain.create_account("bob", 10).unwrap();
+            blockchain.mine_block();
+            blockchain.issue_asset("alice", "PTS", 0, 100).unwrap();
+            blockchain.mine_block();
+
+            // An open network has no authority for the native coin
+            let error = blockchain.mint("alice", "bob", "B", 1).unwrap_err();
+            assert_eq!(error.code(), "unauthorized");
+            let error = blockchain.mint("bob", "bob", "PTS", 1).unwrap_err();
+            assert_eq!(error.code(), "unauthorized");
+
+            blockchain.process_command("mint alice bob PTS 40").unwrap();
+            blockchain.process_command("burn alice PTS 30").unwrap();
+            blockchain.mine_block();
+
+            assert_eq!(blockchain.asset_balance("bob", "PTS").unwrap(), 40);
+            assert_eq!(blockchain.asset_balance("alice", "PTS").unwrap(), 70);
+            assert_eq!(blockchain.asset_info("PTS").unwrap().supply(), Some(110));
+            assert!(blockchain.check_invariants().is_ok());
+        }
+
+        #[test]
+        fn test_deterministic_genesis() {
+            let path = std::env::temp_dir().join("b-test-genesis.json");
+            std::fs::write(
+                &path,
+                r#"{
+                    "chain_id": "b-staging",
+                    "timestamp": 1700000000,
+                    "consensus": { "block_time_secs": 5 },
+                    "allocations": { "alice": 100 }
+                }"#,
+            )
+            .unwrap();
+            let genesis = GenesisConfig::load(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            let first = Blockchain::with_genesis(genesis.clone(), Storage::default());
+            let second = Blockchain::with_genesis(genesis.clone(), Storage::default());
+            assert_eq!(first.genesis_hash(), second.genesis_hash());
+            assert_eq!(first.chain[0].timestamp(), 1700000000);
+            assert_eq!(first.block_time, Duration::from_secs(5));
+            assert_eq!(first.balance("alice").unwrap(), 100);
+
+            let other_network = GenesisConfig {
+                chain_id: "b-load".to_string(),
+                ..genesis
+            };
+            let third = Blockchain::with_genesis(other_network, Storage::default());
+            assert_ne!(first.genesis_hash(), third.genesis_hash());
+        }
+
+        #[test]
+        fn test_peer_genesis_mismatch() {
+            use std::sync::{Arc, Mutex};
+
+            let start = |chain_id: &str, peers: Vec<String>| {
+                let genesis = GenesisConfig {
+                    chain_id: chain_id.to_string(),
+                    ..Default::default()
+                };
+                let mut blockchain = Blockchain::with_genesis(genesis, Storage::default());
+                blockchain.listen_addr = "127.0.0.1:0".to_string();
+                blockchain.peers = peers;
+                Blockchain::start_node(Arc::new(Mutex::new(blockchain)))
+            };
+
+            let peer = start("b-dev", vec![]).unwrap().to_string();
+            assert!(start("b-dev", vec![peer.clone()]).is_ok());
+
+            let error = start("b-staging", vec![peer]).unwrap_err();
+            assert_eq!(error.code(), "genesis_mismatch");
+        }
+
+        #[test]
+        fn test_submitted_nonces_follow_the_sequence() {
+            let mut blockchain = create_clean_blockchain();
+            blockchain.create_account("alice", 100).unwrap();
+            blockchain.create_account("bob", 0).unwrap();
+            blockchain.mine_block();
+            let transfer = |nonce| Transaction::Transfer {
+                chain_id: "b-dev".to_string(),
+                from: "alice".to_string(),
+                to: "bob".to_string(),
+                amount: 1,
+                nonce,
+            };
+
+            let error = blockchain.submit_transaction(transfer(42)).unwrap_err();
+            assert_eq!(error.code(), "invalid_nonce");
+            let command = format!("submit-tx {}", serde_json::to_string(&transfer(1)).unwrap());
+            let error = blockchain.process_command(&command).unwrap_err();
+            assert_eq!(error.code(), "invalid_nonce");
+            assert!(blockchain.pending_transactions.is_empty());
+
+            // Submitted and locally built transfers share one sequence
+            blockchain.submit_transaction(transfer(0)).unwrap();
+            blockchain.transfer("alice", "bob", 2).unwrap();
+            blockchain.submit_transaction(transfer(2)).unwrap();
+            blockchain.mine_block();
+            assert_eq!(blockchain.balance("bob").unwrap(), 4);
+        }
+
+        #[test]
+        fn test_cross_chain_replay_rejected() {
+            let mut staging = create_clean_blockchain();
+            staging.create_account("alice", 100).unwrap();
+            staging.create_account("bob", 0).unwrap();
+            staging.mine_block();
+            staging.transfer("alice", "bob", 10).unwrap();
+            let replayed = staging.pending_transactions[0].clone();
+            assert_eq!(replayed.chain_id(), "b-dev");
+
+            let genesis = GenesisConfig {
+                chain_id: "b-load".to_string(),
+                ..Default::default()
+            };
+            let mut load = Blockchain::with_genesis(genesis, Storage::default());
+            load.is_active = true;
+            let error = load.submit_transaction(replayed.clone()).unwrap_err();
+            assert_eq!(error.code(), "wrong_chain");
+
+            let command = format!("submit-tx {}", serde_json::to_string(&replayed).unwrap());
+            let error = load.process_command(&command).unwrap_err();
+            assert_eq!(error.code(), "wrong_chain");
+            assert!(load.pending_transactions.is_empty());
+
+            // Commands naming another chain are refused before they are parsed
+            let error = load
+                .process_command("create-account carol 0 --chain-id b-dev")
+                .unwrap_err();
+            assert_eq!(error.code(), "wrong_chain");
+            assert!(load
+                .process_command("create-account carol 0 --chain-id b-load")
+                .is_ok());
+
+            // A transaction that reached the mempool anyway is dropped when mined
+            load.pending_transactions.push(replayed);
+            load.mine_block();
+            let block = load.chain.last().unwrap();
+            assert_eq!(block.chain_id(), "b-load");
+            assert!(matches!(
+                block.receipts()[1].status,
+                ReceiptStatus::Dropped { .. }
+            ));
+        }
+
+        #[test]
+        fn test_rewind_restores_ledger_and_mempool() {
+            let mut blockchain = create_clean_blockchain();
+            blockchain.create_account("alice", 1000).unwrap();
+            blockchain.mine_block();
+            let ledger = serde_json::to_value(&blockchain.storage).unwrap();
+
+            blockchain.transfer("alice", "bob", 200).unwrap();
+            blockchain.issue_asset("alice", "GOLD", 2, 500).unwrap();
+            blockchain.mine_block();
+            blockchain.create_account("carol", 0).unwrap();
+            blockchain.mine_block();
+            blockchain.create_account("dave", 0).unwrap();
+            assert_eq!(blockchain.height(), 3);
+
+            let response = blockchain.process_command("rewind 1").unwrap();
+            assert_eq!(
+                response,
+                "Rewound to height 1, 3 transactions returned to the mempool"
+            );
+            assert_eq!(blockchain.height(), 1);
+            assert_eq!(serde_json::to_value(&blockchain.storage).unwrap(), ledger);
+            // Rewound transactions come first, in their original order
+            let pending: Vec<&str> = blockchain
+                .pending_transactions
+                .iter()
+                .flat_map(|tx| tx.accounts())
+                .collect();
+            assert_eq!(pending, ["alice", "bob", "alice", "carol", "dave"]);
+
+            // Mining them again leads to the same ledger
+            blockchain.mine_block();
+            assert_eq!(blockchain.balance("bob").unwrap(), 200);
+            assert_eq!(blockchain.asset_balance("alice", "GOLD").unwrap(), 500);
+            blockchain.check_invariants().unwrap();
+
+            let error = blockchain.process_command("rewind 7").unwrap_err();
+            assert_eq!(error.code(), "invalid_rewind");
+        }
+
+        #[test]
+        fn test_historical_balances() {
+            let mut blockchain = create_clean_blockchain();
+            blockchain.create_account("alice", 1000).unwrap();
+            blockchain.mine_block();
+            blockchain.clock_offset += Duration::from_secs(100);
+            blockchain.transfer("alice", "bob", 300).unwrap();
+            blockchain.issue_asset("alice", "GOLD", 2, 500).unwrap();
+            blockchain.mine_block();
+            blockchain.clock_offset += Duration::from_secs(100);
+            blockchain.transfer("alice", "bob", 200).unwrap();
+            blockchain.mine_block();
+
+            assert_eq!(blockchain.balance_at("alice", "B", 1).unwrap(), 1000);
+            assert_eq!(blockchain.balance_at("alice", "B", 2).unwrap(), 700);
+            assert_eq!(blockchain.balance_at("alice", "GOLD", 1).unwrap(), 0);
+            assert_eq!(blockchain.balance_at("bob", "B", 3).unwrap(), 500);
+            let error = blockchain.balance_at("bob", "B", 1).unwrap_err();
+            assert_eq!(error.code(), "account_not_found");
+            let error = blockchain.balance_at("alice", "B", 9).unwrap_err();
+            assert_eq!(error.code(), "block_not_found");
+
+            let response = blockchain.process_command("balance alice --at 2").unwrap();
+            assert_eq!(response, "Balance of 'alice' at height 2: 700");
+            let timestamp = blockchain.chain[2].timestamp() + 50;
+            let response = blockchain
+                .process_command(&format!("balance bob --at-time {}", timestamp))
+                .unwrap();
+            assert_eq!(response, "Balance of 'bob' at height 2: 300");
+            let response = blockchain.process_command("balances alice --at 2").unwrap();
+            let balances: serde_json::Value = serde_json::from_str(&response).unwrap();
+            assert_eq!(
+                balances["balances"],
+                serde_json::json!({ "B": 700, "GOLD": 500 })
+            );
+        }
+
+        #[test]
+        fn test_finality_and_checkpoints() {
+            let mut blockchain = create_clean_blockchain();
+            blockchain.genesis.consensus.finality_depth = Some(2);
+            let first = blockchain.create_account("alice", 10).unwrap();
+            blockchain.mine_block();
+            for account in ["bob", "carol", "dave"] {
+                blockchain.create_account(account, 0).unwrap();
+                blockchain.mine_block();
+            }
+            assert_eq!(blockchain.finalized_height(), 2);
+            // Final blocks are never rewound, so their undo data is dropped
+            let undone: Vec<u64> = blockchain.storage.undo.keys().copied().collect();
+            assert_eq!(undone, [3, 4]);
+
+            let block = |blockchain: &mut Blockchain, height: u64| -> serde_json::Value {
+                let response = blockchain.process_command(&format!("get-block {}", height));
+                serde_json::from_str(&response.unwrap()).unwrap()
+            };
+            assert_eq!(block(&mut blockchain, 2)["final"], true);
+            assert_eq!(block(&mut blockchain, 3)["final"], false);
+            let status = blockchain
+                .process_command(&format!("tx-status {}", first))
+                .unwrap();
+            assert!(
+                status.ends_with("confirmed at height 1 (final)"),
+                "{}",
+                status
+            );
+
+            // Reorgs never reach below the finalized block
+            let error = blockchain.rewind_to(1).unwrap_err();
+            assert_eq!(error.code(), "below_finality");
+            blockchain.rewind_to(2).unwrap();
+
+            // A checkpoint finalizes its block whatever the depth
+            blockchain.genesis.consensus.finality_depth = None;
+            assert_eq!(blockchain.finalized_height(), 0);
+            let error = blockchain.rewind_to(1).unwrap_err();
+            assert_eq!(error.code(), "undo_missing");
+            let checkpoint = blockchain.chain[2].hash();
+            blockchain.checkpoints.insert(2, checkpoint);
+            assert_eq!(blockchain.finalized_height(), 2);
+
+            // Blocks that contradict a checkpoint are refused
+            let mut other = create_clean_blockchain();
+            other.checkpoints.insert(1, "00".repeat(32));
+            let error = other.validate_block(&blockchain.chain[1]).unwrap_err();
+            assert_eq!(error.code(), "checkpoint_mismatch");
+
+            // Without finality, undo data is kept for the newest blocks only
+            other.retention.undo_depth = 2;
+            for account in ["erin", "frank", "grace", "heidi"] {
+                other.create_account(account, 0).unwrap();
+                other.mine_block();
+            }
+            let undone: Vec<u64> = other.storage.undo.keys().copied().collect();
+            assert_eq!(undone, [3, 4]);
+        }
+
+        #[test]
+        fn test_block_limits_carry_over() {
+            let mut genesis = GenesisConfig::default();
+            genesis.consensus.max_block_transactions = 2;
+            let mut blockchain = Blockchain::with_genesis(genesis, Storage::default());
+            blockchain.is_active = true;
+            for id in ["alice", "bob", "carol"] {
+                blockchain.create_account(id, 10).unwrap();
+            }
+
+            blockchain.mine_block();
+            assert_eq!(blockchain.chain[1].transactions().len(), 2);
+            assert_eq!(blockchain.pending_transactions.len(), 1);
+            blockchain.mine_block();
+            assert_eq!(blockchain.chain[2].transactions().len(), 1);
+            assert!(blockchain.pending_transactions.is_empty());
+            assert_eq!(blockchain.balance("carol").unwrap(), 10);
+
+            // A byte limit that holds a single transaction per block
+            blockchain.genesis.consensus.max_block_transactions = 10;
+            blockchain.transfer("alice", "bob", 1).unwrap();
+            blockchain.transfer("alice", "carol", 1).unwrap();
+            blockchain.genesis.consensus.max_block_bytes =
+                blockchain.pending_transactions[0].size() + 1;
+            blockchain.mine_block();
+            assert_eq!(blockchain.chain[3].transactions().len(), 1);
+            assert_eq!(blockchain.pending_transactions.len(), 1);
+
+            // Transactions that can never fit are refused at admission
+            blockchain.genesis.consensus.max_block_bytes = 10;
+            let error = blockchain.transfer("alice", "bob", 1).unwrap_err();
+            assert_eq!(error.code(), "transaction_too_large");
+        }
+
+        #[test]
+        fn test_oversize_block_rejected_by_validators() {
+            let mut producer = create_clean_blockchain();
+            let mut validator = create_clean_blockchain();
+            validator.genesis.consensus.max_block_transactions = 2;
+            for id in ["alice", "bob", "carol"] {
+                producer.create_account(id, 10).unwrap();
+            }
+            producer.mine_block();
+            let block = &producer.chain[1];
+
+            let error = validator.validate_block(block).unwrap_err();
+            assert_eq!(error.code(), "too_many_transactions");
+
+            validator.genesis.consensus.max_block_transactions = 10;
+            validator.genesis.consensus.max_block_bytes = block
+                .transactions()
+                .iter()
+                .map(Transaction::size)
+                .sum::<usize>()
+                - 1;
+            let error = validator.validate_block(block).unwrap_err();
+            assert_eq!(error.code(), "block_too_large");
+
+            validator.genesis.consensus = producer.genesis.consensus.clone();
+            assert!(validator.validate_block(block).is_ok());
+            assert_eq!(
+                validator
+                    .validate_block(&producer.chain[0])
+                    .unwrap_err()
+                    .code(),
+                "invalid_parent"
+            );
+        }
+
+        #[test]
+        fn test_binary_encoding_roundtrip() {
+            let mut blockchain = create_clean_blockchain();
+            blockchain.create_account("alice", 100).unwrap();
+            blockchain.create_account("bob", 0).unwrap();
+            blockchain.mine_block();
+            blockchain.transfer("alice", "bob", 70).unwrap();
+            blockchain.transfer("alice", "bob", 30).unwrap();
+            blockchain.mine_block();
+
+            for block in &blockchain.chain {
+                let encoded = encoding::encode(block);
+                let decoded: Block = encoding::decode(&encoded).unwrap();
+                assert_eq!(&decoded, block);
+                assert_eq!(decoded.hash(), block.hash());
+                // Re-encoding yields the same bytes, so hashes are canonical
+                assert_eq!(encoding::encode(&decoded), encoded);
+            }
+            let tx = &blockchain.chain[2].transactions()[1];
+            let encoded = encoding::encode(tx);
+            assert_eq!(tx.size(), encoded.len());
+            assert!(tx.size() < serde_json::to_vec(tx).unwrap().len());
+
+            let mut wrong_version = encoded.clone();
+            wrong_version[0] = 2;
+            assert_eq!(
+                encoding::decode::<Transaction>(&wrong_version).unwrap_err(),
+                DecodeError::UnsupportedVersion(2)
+            );
+            let mut trailing = encoded.clone();
+            trailing.push(0);
+            assert_eq!(
+                encoding::decode::<Transaction>(&trailing).unwrap_err(),
+                DecodeError::TrailingBytes(1)
+            );
+            assert_eq!(
+                encoding::decode::<Transaction>(&encoded[..encoded.len() - 1]).unwrap_err(),
+                DecodeError::UnexpectedEnd
+            );
+            // A zero padded varint is not the canonical encoding of 0
+            assert!(encoding::decode::<u64>(&[encoding::VERSION, 0x80, 0x00]).is_err());
+        }
+
+        #[test]
+        fn test_tracing_output() {
+            use blockchain_simulation::client;
+            use std::sync::{Arc, Mutex};
+            use tracing::span::{Attributes, Id};
+            use tracing::Subscriber;
+            use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+            const CHILD: &str = "B_TRACING_TEST_CHILD";
+            let exercise = || {
+                let mut blockchain = create_clean_blockchain();
+                blockchain.listen_addr = "127.0.0.1:0".to_string();
+                blockchain.create_account("alice", 10).unwrap();
+                blockchain.mine_block();
+                blockchain.mine_block(); // Nothing to mine, which is logged
+                let addr = Blockchain::start_node(Arc::new(Mutex::new(blockchain)))
+                    .unwrap()
+                    .to_string();
+                client::send_command(&addr, "balance alice").unwrap();
+                let _ = client::send_command(&addr, "balance nobody");
+            };
+
+            // Without a subscriber the library prints nothing; checked in a
+            // child process, since libtest does not capture direct writes
+            if std::env::var_os(CHILD).is_some() {
+                println!("<start>");
+                exercise();
+                println!("<end>");
+                return;
+            }
+            let output = std::process::Command::new(std::env::current_exe().unwrap())
+                .args([
+                    "--exact",
+                    "tests::tests::test_tracing_output",
+                    "--nocapture",
+                ])
+                .env(CHILD, "1")
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let between = stdout
+                .split_once("<start>")
+                .and_then(|(_, rest)| rest.split_once("<end>"))
+                .map(|(between, _)| between)
+                .expect("the child ran the library");
+            assert_eq!(between.trim(), "");
+
+            // With a subscriber, work happens inside `block` and `connection` spans
+            struct SpanNames(Arc<Mutex<Vec<&'static str>>>);
+            impl<S: Subscriber> Layer<S> for SpanNames {
+                fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+                    self.0.lock().unwrap().push(attrs.metadata().name());
+                }
+            }
+            let names = Arc::new(Mutex::new(Vec::new()));
+            let subscriber = tracing_subscriber::registry().with(SpanNames(names.clone()));
+            tracing::subscriber::set_global_default(subscriber).unwrap();
+            exercise();
+            let names = names.lock().unwrap();
+            assert!(names.contains(&"block"), "{:?}", names);
+            assert!(names.contains(&"connection"), "{:?}", names);
+        }
+
+        #[test]
+        fn test_binary_peer_messages() {
+            use blockchain_simulation::client;
+            use std::sync::{Arc, Mutex};
+
+            let mut blockchain = create_clean_blockchain();
+            blockchain.listen_addr = "127.0.0.1:0".to_string();
+            let genesis_hash = blockchain.genesis_hash();
+            let addr = Blockchain::start_node(Arc::new(Mutex::new(blockchain)))
+                .unwrap()
+                .to_string();
+
+            match client::send_message(&addr, &Message::GetChainInfo).unwrap() {
+                Message::ChainInfo {
+                    chain_id,
+                    genesis_hash: hash,
+                    height,
+                } => {
+                    assert_eq!(chain_id, "b-dev");
+                    assert_eq!(hash, genesis_hash);
+                    assert_eq!(height, 0);
+                }
+                other => panic!("unexpected response {:?}", other),
+            }
+
+            let mut other = create_clean_blockchain();
+            other.create_account("carol", 10).unwrap();
+            let tx = other.pending_transactions[0].clone();
+            let response = client::send_message(&addr, &Message::SubmitTransaction(tx.clone()));
+            assert!(matches!(response.unwrap(), Message::Submitted(id) if id == tx.hash()));
+
+            match client::send_message(&addr, &Message::GetBlock(7)).unwrap() {
+                Message::Error { code, .. } => assert_eq!(code, "block_not_found"),
+                other => panic!("unexpected response {:?}", other),
+            }
+            // Text commands keep working on the same port
+            let response = client::send_command(&addr, "mempool").unwrap();
+            assert!(response.contains(&tx.hash()));
+        }
+
+        #[test]
+        fn test_wait_for_transaction() {
+            use blockchain_simulation::client::{self, Session};
+            use blockchain_simulation::error::NetworkError;
+            use std::sync::{Arc, Mutex};
+            use std::time::Duration;
+
+            let mut blockchain = create_clean_blockchain();
+            blockchain.listen_addr = "127.0.0.1:0".to_string();
+            let addr = Blockchain::start_node(Arc::new(Mutex::new(blockchain)))
+                .unwrap()
+                .to_string();
+            let submit = |command: &str| {
+                let response = client::send_command(&addr, command).unwrap();
+                client::submitted_tx_id(&response).unwrap()
+            };
+            let timeout = Duration::from_secs(10);
+
+            let first = submit("create-account alice 0");
+            let settled =
+                client::wait_for_transaction(&addr, &Session::default(), &first, 1, timeout)
+                    .unwrap();
+            assert_eq!(settled.status, TxStatus::Confirmed { height: 1 });
+            assert_eq!(settled.receipt, ReceiptStatus::Included);
+            assert_eq!(settled.confirmations, 1);
+
+            let second = submit("create-account bob 0");
+            client::wait_for_transaction(&addr, &Session::default(), &second, 1, timeout).unwrap();
+            let settled =
+                client::wait_for_transaction(&addr, &Session::default(), &first, 2, timeout)
+                    .unwrap();
+            assert_eq!(settled.confirmations, 2);
+
+            // Nothing else is mined, so a third confirmation never comes
+            let result = client::wait_for_transaction(
+                &addr,
+                &Session::default(),
+                &first,
+                3,
+                Duration::from_millis(300),
+            );
+            assert!(matches!(result, Err(NetworkError::Timeout(_))));
+
+            match client::wait_for_transaction(&addr, &Session::default(), "unknown", 1, timeout) {
+                Err(NetworkError::Remote { code, .. }) => assert_eq!(code, "transaction_not_found"),
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+
+        #[test]
+        fn test_command_port_auth_scopes() {
+            use blockchain_simulation::auth::{AuthConfig, Scope};
+            use blockchain_simulation::client::{self, Session};
+            use std::sync::{Arc, Mutex};
+
+            let mut blockchain = create_clean_blockchain();
+            blockchain.create_account("alice", 10).unwrap();
+            blockchain.mine_block();
+            blockchain.issue_asset("alice", "GOLD", 0, 100).unwrap();
+            blockchain.mine_block();
+            // Only sessions that may act for the issuer mint its asset
+            let error = blockchain
+                .process_command_as("mint alice bob GOLD 1000", &Scope::Submit.into())
+                .unwrap_err();
+            assert_eq!(error.code(), "account_not_bound");
+            blockchain.listen_addr = "127.0.0.1:0".to_string();
+            blockchain.auth = Some(AuthConfig {
+                anonymous: Some(Scope::Read),
+                tokens: [
+                    ("submitter".to_string(), Scope::Submit),
+                    ("operator".to_string(), Scope::Admin),
+                    ("alice-key".to_string(), Scope::Submit),
+                ]
+                .into(),
+                accounts: [("alice-key".to_string(), "alice".to_string())].into(),
+            });
+            let mut other = create_clean_blockchain();
+            other.create_account("alice", 10).unwrap();
+            let tx = other.pending_transactions[0].clone();
+            let addr = Blockchain::start_node(Arc::new(Mutex::new(blockchain)))
+                .unwrap()
+                .to_string();
+            let code = |token: Option<&str>, command: &str| {
+                let session = Session {
+                    token: token.map(str::to_string),
+                    ..Session::default()
+                };
+                let response = client::send_command_as(&addr, &session, command).unwrap();
+                Error::parse_wire(&response).map(|(code, _)| code.to_string())
+            };
+
+            assert_eq!(code(None, "height"), None);
+            assert_eq!(
+                code(None, "create-account bob 0").as_deref(),
+                Some("permission_denied")
+            );
+            assert_eq!(code(Some("submitter"), "create-account bob 0"), None);
+            assert_eq!(
+                code(Some("submitter"), "mine").as_deref(),
+                Some("permission_denied")
+            );
+            assert_eq!(code(Some("operator"), "mine"), None);
+            assert_eq!(
+                code(Some("guess"), "height").as_deref(),
+                Some("invalid_token")
+            );
+            assert_eq!(
+                code(Some("submitter"), "mint alice alice GOLD 5").as_deref(),
+                Some("account_not_bound")
+            );
+            assert_eq!(
+                code(Some("alice-key"), "burn bob GOLD 5").as_deref(),
+                Some("account_not_bound")
+            );
+            assert_eq!(code(Some("alice-key"), "mint alice alice GOLD 5"), None);
+            assert_eq!(code(Some("operator"), "burn alice GOLD 5"), None);
+
+            // Binary messages carry the same header
+            let submit = Message::SubmitTransaction(tx.clone());
+            match client::send_message_as(&addr, &Session::default(), &submit).unwrap() {
+                Message::Error { code, message } => {
+                    assert_eq!(code, "permission_denied");
+                    assert!(message.contains("'submit-transaction'"));
+                }
+                other => panic!("unexpected response {:?}", other),
+            }
+            let response =
+                client::send_message_as(&addr, &Session::with_token("submitter"), &submit).unwrap();
+            assert!(matches!(response, Message::Submitted(id) if id == tx.hash()));
+
+            let closed = AuthConfig::default();
+            assert_eq!(
+                closed.authorize(None).unwrap_err().code(),
+                "unauthenticated"
+            );
+        }
+
+        #[test]
+        fn test_encrypted_sessions() {
+            use blockchain_simulation::client::{self, Encryption, Session};
+            use blockchain_simulation::error::NetworkError;
+            use blockchain_simulation::transport::{SecureStream, StaticKey};
+            use std::net::{TcpListener, TcpStream};
+            use std::sync::{Arc, Mutex};
+
+            let node_key = StaticKey::generate();
+            let mut blockchain = create_clean_blockchain();
+            blockchain.listen_addr = "127.0.0.1:0".to_string();
+            blockchain.node_key = Some(node_key.clone());
+            blockchain.require_encryption = true;
+            let addr = Blockchain::start_node(Arc::new(Mutex::new(blockchain)))
+                .unwrap()
+                .to_string();
+            let encrypted = |node_key: Option<&[u8]>| Session {
+                token: None,
+                encryption: Some(Encryption {
+                    key: StaticKey::generate(),
+                    node_key: node_key.map(<[u8]>::to_vec),
+                }),
+            };
+
+            let response = client::send_command(&addr, "height").unwrap();
+            assert_eq!(
+                Error::parse_wire(&response).map(|(code, _)| code),
+                Some("encryption_required")
+            );
+            let session = encrypted(Some(node_key.public()));
+            let response = client::send_command_as(&addr, &session, "height").unwrap();
+            assert_eq!(response, r#"{"height":0}"#);
+            let response = client::send_message_as(&addr, &session, &Message::GetChainInfo);
+            assert!(matches!(
+                response.unwrap(),
+                Message::ChainInfo { height: 0, .. }
+            ));
+
+            let impostor = StaticKey::generate();
+            let result =
+                client::send_command_as(&addr, &encrypted(Some(impostor.public())), "height");
+            assert!(matches!(result, Err(NetworkError::KeyMismatch { .. })));
+
+            // A secure node only syncs with peers whose key is pinned
+            let secure_peer = |peer: String| {
+                let mut blockchain = create_clean_blockchain();
+                blockchain.listen_addr = "127.0.0.1:0".to_string();
+                blockchain.node_key = Some(StaticKey::generate());
+                blockchain.require_encryption = true;
+                blockchain.peers = vec![peer];
+                Blockchain::start_node(Arc::new(Mutex::new(blockchain)))
+            };
+            let error = secure_peer(addr.clone()).unwrap_err();
+            assert_eq!(error.code(), "unpinned_peer");
+            let pinned = format!("{}@{}", addr, node_key.public_hex());
+            secure_peer(pinned).unwrap();
+
+            // Messages larger than one Noise frame are split and reassembled
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let local = listener.local_addr().unwrap();
+            let server = std::thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut tag = [0; 1];
+                std::io::Read::read_exact(&mut stream, &mut tag).unwrap();
+                let mut stream = SecureStream::accept(stream, &node_key).unwrap();
+                let request = stream.receive().unwrap();
+                stream.send(&request).unwrap();
+            });
+            let client_key = StaticKey::generate();
+            let stream = TcpStream::connect(local).unwrap();
+            let mut stream = SecureStream::connect(stream, &client_key, None).unwrap();
+            let payload: Vec<u8> = (0..200_000).map(|i| i as u8).collect();
+            stream.send(&payload).unwrap();
+            assert_eq!(stream.receive().unwrap(), payload);
+            server.join().unwrap();
+
+            // Key files are private to their owner
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+
+                let path = std::env::temp_dir().join("b-test-node.key");
+                let _ = std::fs::remove_file(&path);
+                let key = StaticKey::load_or_generate(&path).unwrap();
+                let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+                assert_eq!(mode & 0o777, 0o600);
+                let loaded = StaticKey::load_or_generate(&path).unwrap();
+                assert_eq!(loaded.public(), key.public());
+                std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+                let error = StaticKey::load_or_generate(&path).unwrap_err();
+                std::fs::remove_file(&path).unwrap();
+                assert_eq!(error.code(), "key_exposed");
+            }
+        }
+
+        #[test]
+        fn test_connection_limits() {
+            use blockchain_simulation::auth::{AuthConfig, Scope};
+            use blockchain_simulation::client::{self, Session};
+            use blockchain_simulation::limits::ConnectionLimits;
+            use std::io::Read;
+            use std::net::TcpStream;
+            use std::sync::{Arc, Mutex};
+            use std::time::Duration;
+
+            let mut blockchain = create_clean_blockchain();
+            blockchain.listen_addr = "127.0.0.1:0".to_string();
+            blockchain.auth = Some(AuthConfig {
+                anonymous: Some(Scope::Admin),
+                ..AuthConfig::default()
+            });
+            blockchain.limits = ConnectionLimits {
+                max_connections: 2,
+                read_timeout: Duration::from_millis(200),
+                max_request_bytes: 64,
+                requests_per_second: 0.1,
+                burst: 2.0,
+            };
+            let metrics = blockchain.metrics.clone();
+            let addr = Blockchain::start_node(Arc::new(Mutex::new(blockchain)))
+                .unwrap()
+                .to_string();
+            let code = |command: &str| {
+                let response = client::send_command(&addr, command).unwrap();
+                Error::parse_wire(&response).map(|(code, _)| code.to_string())
+            };
+
+            assert_eq!(code("height"), None);
+            assert_eq!(code("height"), None);
+            assert_eq!(code("height").as_deref(), Some("rate_limited"));
+            // Made-up tokens share the bucket of their IP
+            for attempt in 0..3 {
+                let session = Session::with_token(&format!("bogus-{}", attempt));
+                let response = client::send_command_as(&addr, &session, "height").unwrap();
+                assert_eq!(
+                    Error::parse_wire(&response).map(|(code, _)| code),
+                    Some("rate_limited")
+                );
+            }
+            assert_eq!(
+                code(&format!("balance {}", "a".repeat(64))).as_deref(),
+                Some("request_too_large")
+            );
+
+            // Silent clients hold the connections until their reads time out
+            let idle: Vec<TcpStream> = (0..2).map(|_| TcpStream::connect(&addr).unwrap()).collect();
+            std::thread::sleep(Duration::from_millis(50));
+            assert_eq!(code("height").as_deref(), Some("too_many_connections"));
+            match client::send_message(&addr, &Message::GetChainInfo).unwrap() {
+                Message::Error { code, .. } => assert_eq!(code, "too_many_connections"),
+                other => panic!("unexpected response {:?}", other),
+            }
+            for mut stream in idle {
+                let mut response = String::new();
+                stream.read_to_string(&mut response).unwrap();
+                assert!(response.starts_with("Error [timeout]"), "{}", response);
+            }
+
+            // The timeout covers the whole request, not each read
+            let mut stream = TcpStream::connect(&addr).unwrap();
+            let mut trickle = stream.try_clone().unwrap();
+            let started = std::time::Instant::now();
+            std::thread::spawn(move || {
+                for byte in b"height" {
+                    if std::io::Write::write_all(&mut trickle, &[*byte]).is_err() {
+                        break;
+                    }
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+            });
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with("Error [timeout]"), "{}", response);
+            assert!(started.elapsed() < Duration::from_millis(500));
+
+            let rendered = metrics.render();
+            for (reason, count) in [
+                ("rate_limited", 4),
+                ("request_too_large", 1),
+                ("too_many_connections", 2),
+                ("timeout", 3),
+            ] {
+                let line = format!(
+                    "blockchain_requests_refused_total{{reason=\"{}\"}} {}",
+                    reason, count
+                );
+                assert!(rendered.contains(&line), "missing {}", line);
+            }
+        }
+
+        #[test]
+        fn test_block_log_survives_restart() {
+            let path = std::env::temp_dir().join("b-test-block-log.db");
+            let blocks = path.with_extension("blocks");
+            let _ = std::fs::remove_file(&path);
+            let _ = std::fs::remove_file(&blocks);
+
+            let mut blockchain =
+                Blockchain::with_genesis(GenesisConfig::default(), Storage::open(&path).unwrap());
+            blockchain.is_active = true;
+            blockchain.create_account("alice", 100).unwrap();
+            blockchain.mine_block();
+            blockchain.create_account("bob", 5).unwrap();
+            blockchain.mine_block();
+            let tip = blockchain.chain[2].hash();
+
+            let restarted =
+                Blockchain::with_genesis(GenesisConfig::default(), Storage::open(&path).unwrap());
+            std::fs::remove_file(&path).unwrap();
+            std::fs::remove_file(&blocks).unwrap();
+            assert_eq!(restarted.height(), 2);
+            assert!(restarted
+                .metrics
+                .render()
+                .contains("blockchain_chain_height 2\n"));
+            assert_eq!(restarted.chain[2].hash(), tip);
+            assert_eq!(restarted.balance("bob").unwrap(), 5);
+        }
+
+        #[test]
+        fn test_unreadable_ledger_is_not_replaced() {
+            let path = std::env::temp_dir().join("b-test-unreadable.db");
+            let ledger = r#"{"version":99,"accounts":{"alice":{"balance":100}}}"#;
+            std::fs::write(&path, ledger).unwrap();
+
+            let error = Storage::open(&path).err().unwrap();
+            let contents = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(error.code(), "storage_version");
+            assert_eq!(contents, ledger);
+        }
+
+        #[test]
+        fn test_pruned_node_recovers_from_snapshot() {
+            use blockchain_simulation::retention::{NodeMode, Retention};
+
+            let path = std::env::temp_dir().join("b-test-pruning.db");
+            let files = ["db", "blocks", "headers", "snapshot.2", "snapshot.4"]
+                .map(|extension| path.with_extension(extension));
+            for file in &files {
+                let _ = std::fs::remove_file(file);
+            }
+
+            let mut blockchain =
+                Blockchain::with_genesis(GenesisConfig::default(), Storage::open(&path).unwrap());
+            blockchain.is_active = true;
+            blockchain.retention = Retention {
+                mode: NodeMode::Pruned,
+                keep_blocks: 2,
+                snapshot_interval: Some(2),
+                ..Retention::default()
+            };
+            for account in ["alice", "bob", "carol", "dave", "erin"] {
+                blockchain.create_account(account, 10).unwrap();
+                blockchain.mine_block();
+            }
+            // Bodies older than the window go, but never above the latest snapshot
+            let unsnapshotted = Retention {
+                snapshot_interval: None,
+                ..blockchain.retention.clone()
+            };
+            assert_eq!(unsnapshotted.prune_height(5, None), 0);
+            assert_eq!(blockchain.storage.snapshots(), [2, 4]);
+            assert_eq!(blockchain.pruned_height(), 3);
+            let block: serde_json::Value =
+                serde_json::from_str(&blockchain.process_command("get-block 1").unwrap()).unwrap();
+            assert_eq!(block["pruned"], true);
+            assert_eq!(block["transactions"], serde_json::json!([]));
+            match blockchain.process_message(Message::GetBlock(1)) {
+                Message::Error { code, .. } => assert_eq!(code, "block_pruned"),
+                other => panic!("unexpected response {:?}", other),
+            }
+            let error = blockchain.rewind_to(2).unwrap_err();
+            assert_eq!(error.code(), "block_pruned");
+            let tip = blockchain.chain[5].hash();
+
+            // With the ledger lost, the node restores the latest snapshot and
+            // replays the blocks mined after it
+            std::fs::remove_file(&path).unwrap();
+            let restarted =
+                Blockchain::with_genesis(GenesisConfig::default(), Storage::open(&path).unwrap());
+            for file in &files {
+                let _ = std::fs::remove_file(file);
+            }
+            assert_eq!(restarted.height(), 5);
+            assert_eq!(restarted.pruned_height(), 3);
+            assert_eq!(restarted.chain[5].hash(), tip);
+            assert_eq!(restarted.storage.height, Some(5));
+            assert_eq!(restarted.balance("erin").unwrap(), 10);
+            restarted.check_invariants().unwrap();
+        }
+
+        #[test]
+        fn test_storage_migration() {
+            use blockchain_simulation::migration::CURRENT_VERSION;
+
+            let path = std::env::temp_dir().join("b-test-migration.db");
+            let backup = std::env::temp_dir().join("b-test-migration.db.v0.bak");
+            let legacy = r#"{
+                "accounts": { "alice": 80, "bob": 20 },
+                "history": { "alice": [{
+                    "height": 1, "tx_index": 0, "direction": "created",
+                    "counterparty": null, "amount": 80, "balance": 80
+                }] }
+            }"#;
+            std::fs::write(&path, legacy).unwrap();
+
+            let report = Storage::migrate_file(&path, true).unwrap();
+            assert_eq!((report.from, report.to), (0, CURRENT_VERSION));
+            assert_eq!(report.steps.len(), 4);
+            assert_eq!(report.steps[0].changes[0], "set minted supply to 100");
+            assert_eq!(report.steps[1].changes[0], "tagged 1 history entries as B");
+            assert_eq!(report.steps[2].changes[0], "started with no undo data");
+            assert!(report.steps[3].changes.is_empty());
+            // A dry run leaves the file alone
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), legacy);
+
+            Storage::migrate_file(&path, false).unwrap();
+            assert_eq!(std::fs::read_to_string(&backup).unwrap(), legacy);
+            let storage = Storage::load_from(&path).unwrap();
+            assert_eq!(storage.minted, 100);
+            assert_eq!(storage.history["alice"][0].asset, "B");
+            assert!(storage.check_invariants().is_ok());
+            assert!(Storage::migrate_file(&path, false).unwrap().is_up_to_date());
+
+            // Ledgers holding fields an older build would drop are too new for it
+            let saved: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            assert_eq!(saved["version"], CURRENT_VERSION);
+
+            std::fs::write(&path, r#"{ "version": 99, "accounts": {} }"#).unwrap();
+            let error = Error::from(Storage::load_from(&path).err().unwrap());
+            assert_eq!(error.code(), "storage_version");
+            std::fs::remove_file(&path).unwrap();
+            std::fs::remove_file(&backup).unwrap();
+        }
+
+        #[test]
+        fn test_repl_target_and_completion() {
+            use blockchain_simulation::repl::{self, Target};
+
+            let mut target = Target::Embedded(Box::new(create_clean_blockchain()));
+            target.execute("create-account alice 100");
+            target.execute("create-account albert 0");
+            assert_eq!(target.execute("mine"), "Mined block 1 with 2 transactions");
+            assert_eq!(target.execute("mine"), "No transactions to mine");
+            assert_eq!(target.execute("balance alice"), "Balance of 'alice': 100");
+            assert!(target
+                .execute("bogus")
+                .starts_with("Error [invalid_command]"));
+
+            let accounts = target.accounts();
+            assert_eq!(accounts, vec!["albert", "alice"]);
+            assert_eq!(
+                repl::complete_word("tr", 2, &accounts),
+                (
+                    0,
+                    vec!["transfer".to_string(), "transfer-asset".to_string()]
+                )
+            );
+            assert_eq!(
+                repl::complete_word("transfer ali", 12, &accounts),
+                (9, vec!["alice".to_string()])
+            );
+            assert_eq!(repl::complete_word("balance al", 10, &accounts).1.len(), 2);
+            assert_eq!(
+                repl::complete_word("adv", 3, &accounts),
+                (0, vec!["advance-time".to_string()])
+            );
+        }
+
+        #[test]
+        fn test_run_script_with_assertions() {
+            use blockchain_simulation::repl::Target;
+            use blockchain_simulation::script::{self, ScriptSummary};
+
+            let passing = "\
+                # alice pays bob
+                let payment = 200
+                create-account alice 1000
+                create-account bob 0
+                mine
+                transfer bob alice $payment
+                expect error insufficient_funds
+                transfer alice bob $payment
+                advance-time 3600
+                mine
+                expect balance alice == 800
+                expect balance bob >= $payment
+                expect height == 2
+            ";
+            let mut target = Target::Embedded(Box::new(create_clean_blockchain()));
+            let mut output = Vec::new();
+            let summary = script::run(&mut target, passing.as_bytes(), &mut output).unwrap();
+            assert_eq!(
+                summary,
+                ScriptSummary {
+                    commands: 7,
+                    assertions: 4
+                }
+            );
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("> transfer alice bob 200"));
+            assert!(output.contains("ok: balance alice == 800"));
+            let Target::Embedded(blockchain) = &target else {
+                unreachable!()
+            };
+            assert!(blockchain.chain[2].timestamp() >= blockchain.chain[1].timestamp() + 3600);
+
+            let failing = "create-account carol 5\nmine\n\nexpect balance carol == 6\n";
+            let error = script::run(&mut target, failing.as_bytes(), std::io::sink()).unwrap_err();
+            assert_eq!(error.line(), Some(4));
+            assert_eq!(
+                error.to_string(),
+                "line 4: expected balance carol == 6, got 5"
+            );
+
+            let error = script::run(&mut target, "balance $nobody".as_bytes(), std::io::sink())
+                .unwrap_err();
+            assert_eq!(error.to_string(), "line 1: undefined variable $nobody");
+        }
     }
 }